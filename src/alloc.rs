@@ -72,7 +72,7 @@ fn test_overflow() {
 
     let x: &mut u64 = arena.alloc(3);
     assert_eq!(*x, 3);
-    assert_eq!(arena.data.len(), 2);
+    assert_eq!(unsafe { (*arena.data.get()).len() }, 2);
 }
 
 #[test]
@@ -0,0 +1,343 @@
+use crate::font::{find_table, read_u16, read_u32};
+use crate::png::*;
+
+use std::collections::{HashMap, HashSet};
+
+// One layer of a COLR glyph: an outline glyph filled with a palette color, or with the
+// text color when `color` is None.
+#[derive(Copy, Clone, Debug)]
+pub struct ColorLayer {
+    pub glyph: u16,
+    pub color: Option<[u8; 4]>,
+}
+
+// A rendered color glyph with straight alpha. (left, top) is the offset of its top left
+// corner from the pen position.
+pub struct ColorBitmap {
+    pub left: i32,
+    pub top: i32,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Copy, Clone)]
+enum BitmapTable { Sbix, Cbdt }
+
+// The color glyphs of a font, either layered outlines from COLR/CPAL or embedded PNG
+// bitmaps from sbix or CBDT/CBLC.
+pub struct ColorGlyphs {
+    layers: HashMap<u16, Vec<ColorLayer>>,
+    bitmap_table: Option<BitmapTable>,
+    // (ppem, offset of the strike within its table), sorted by ppem
+    strikes: Vec<(u16, usize)>,
+    bitmaps: HashSet<u16>,
+}
+
+// A PNG bitmap of a glyph in one strike, with its position in the strike's pixels.
+struct Bitmap<'a> {
+    ppem: u16,
+    left: i32,
+    top: i32,
+    width: u32,
+    height: u32,
+    png: &'a [u8],
+}
+
+impl<'a> Bitmap<'a> {
+    // Position and size of the bitmap once resampled from the strike size to `scale`.
+    fn scaled_bounds(&self, scale: u32) -> [i32; 4] {
+        let factor = scale as f32 / self.ppem as f32;
+        [
+            (self.left as f32 * factor).floor() as i32,
+            (self.top as f32 * factor).floor() as i32,
+            ((self.width as f32 * factor).ceil() as i32).max(1),
+            ((self.height as f32 * factor).ceil() as i32).max(1),
+        ]
+    }
+}
+
+impl ColorGlyphs {
+    pub fn new(data: &[u8]) -> ColorGlyphs {
+        let layers = find_table(data, b"COLR")
+            .and_then(|colr| color_layers(colr, find_table(data, b"CPAL")))
+            .unwrap_or_default();
+        let (bitmap_table, mut strikes) = if let Some(sbix) = find_table(data, b"sbix") {
+            (Some(BitmapTable::Sbix), sbix_strikes(sbix).unwrap_or_default())
+        } else if let Some(cblc) = find_table(data, b"CBLC") {
+            (Some(BitmapTable::Cbdt), cblc_strikes(cblc).unwrap_or_default())
+        } else {
+            (None, Vec::new())
+        };
+        // bitmaps are scaled by the requested size over the strike's, so a 0 ppem strike is unusable
+        strikes.retain(|strike| strike.0 > 0);
+        strikes.sort_by_key(|strike| strike.0);
+
+        let mut glyphs = ColorGlyphs { layers, bitmap_table, strikes, bitmaps: HashSet::new() };
+        let num_glyphs = find_table(data, b"maxp").and_then(|maxp| read_u16(maxp, 4)).unwrap_or(0);
+        let bitmaps = (0..num_glyphs)
+            .filter(|&glyph| glyphs.strikes.iter().any(|&strike| glyphs.strike_bitmap(data, strike, glyph).is_some()))
+            .collect();
+        glyphs.bitmaps = bitmaps;
+        glyphs
+    }
+
+    pub fn contains(&self, glyph: u16) -> bool {
+        self.layers.contains_key(&glyph) || self.bitmaps.contains(&glyph)
+    }
+
+    // Whether any layer of the glyph is drawn in the text color, so that its rendering
+    // depends on that color.
+    pub fn uses_foreground(&self, glyph: u16) -> bool {
+        self.layers.get(&glyph).map_or(false, |layers| layers.iter().any(|layer| layer.color.is_none()))
+    }
+
+    // Offset of the rendered glyph's top left corner from the pen position, in pixels.
+    pub fn offset(&self, font: &font_rs::font::Font, data: &[u8], glyph: u16, scale: u32) -> Option<[i32; 2]> {
+        let [left, top, _, _] = self.bounds(font, data, glyph, scale)?;
+        Some([left, top])
+    }
+
+    // Layers without a palette color are filled with `foreground`, given as straight alpha.
+    pub fn rasterize(&self, font: &font_rs::font::Font, data: &[u8], glyph: u16, scale: u32, foreground: [u8; 4]) -> Option<ColorBitmap> {
+        if let Some(layers) = self.layers.get(&glyph) {
+            let [left, top, width, height] = layer_bounds(font, layers, scale)?;
+            let (width, height) = (width as usize, height as usize);
+            // composited with premultiplied alpha
+            let mut pixels = vec![0.0; width * height * 4];
+            for layer in layers {
+                let (bbox, rendered) = match (font.get_bbox(layer.glyph, scale), font.render_glyph(layer.glyph, scale)) {
+                    (Some(bbox), Some(rendered)) => (bbox, rendered),
+                    _ => continue,
+                };
+                let color = layer.color.unwrap_or(foreground);
+                let (x0, y0) = ((bbox.l - left) as usize, (bbox.t - top) as usize);
+                for y in 0..rendered.height.min(height - y0) {
+                    for x in 0..rendered.width.min(width - x0) {
+                        let alpha = rendered.data[y * rendered.width + x] as f32 / 255.0 * color[3] as f32 / 255.0;
+                        let i = ((y0 + y) * width + x0 + x) * 4;
+                        for c in 0..3 {
+                            pixels[i + c] = color[c] as f32 / 255.0 * alpha + pixels[i + c] * (1.0 - alpha);
+                        }
+                        pixels[i + 3] = alpha + pixels[i + 3] * (1.0 - alpha);
+                    }
+                }
+            }
+            let data = pixels.chunks(4).flat_map(|pixel| unpremultiply([pixel[0], pixel[1], pixel[2], pixel[3]]).to_vec()).collect();
+            Some(ColorBitmap { left, top, width: width as u32, height: height as u32, data })
+        } else {
+            let bitmap = self.find_bitmap(data, glyph, scale)?;
+            let (png_width, png_height, pixels) = decode_png(bitmap.png)?;
+            let [left, top, width, height] = bitmap.scaled_bounds(scale);
+            let data = resample(&pixels, png_width, png_height, width as usize, height as usize);
+            Some(ColorBitmap { left, top, width: width as u32, height: height as u32, data })
+        }
+    }
+
+    fn bounds(&self, font: &font_rs::font::Font, data: &[u8], glyph: u16, scale: u32) -> Option<[i32; 4]> {
+        if let Some(layers) = self.layers.get(&glyph) {
+            layer_bounds(font, layers, scale)
+        } else {
+            self.find_bitmap(data, glyph, scale).map(|bitmap| bitmap.scaled_bounds(scale))
+        }
+    }
+
+    // Bitmaps are taken from the smallest strike at least as large as `scale`, or the
+    // largest strike if there is none, and resampled to size.
+    fn find_bitmap<'a>(&self, data: &'a [u8], glyph: u16, scale: u32) -> Option<Bitmap<'a>> {
+        if !self.bitmaps.contains(&glyph) { return None; }
+        let strike = self.strikes.iter().find(|strike| strike.0 as u32 >= scale).or(self.strikes.last())?;
+        self.strike_bitmap(data, *strike, glyph)
+    }
+
+    fn strike_bitmap<'a>(&self, data: &'a [u8], (ppem, offset): (u16, usize), glyph: u16) -> Option<Bitmap<'a>> {
+        match self.bitmap_table? {
+            BitmapTable::Sbix => sbix_bitmap(find_table(data, b"sbix")?, ppem, offset, glyph),
+            BitmapTable::Cbdt => cbdt_bitmap(find_table(data, b"CBLC")?, find_table(data, b"CBDT")?, ppem, offset, glyph),
+        }
+    }
+}
+
+// Reads the layers of every base glyph in a version 0 COLR table, with colors from the
+// first CPAL palette.
+fn color_layers(colr: &[u8], cpal: Option<&[u8]>) -> Option<HashMap<u16, Vec<ColorLayer>>> {
+    let num_base_glyphs = read_u16(colr, 2)? as usize;
+    let base_glyphs = read_u32(colr, 4)? as usize;
+    let layer_records = read_u32(colr, 8)? as usize;
+    let palette = cpal.and_then(first_palette).unwrap_or_default();
+
+    let mut layers = HashMap::new();
+    for i in 0..num_base_glyphs {
+        let record = base_glyphs + 6 * i;
+        let glyph = read_u16(colr, record)?;
+        let first_layer = read_u16(colr, record + 2)? as usize;
+        let num_layers = read_u16(colr, record + 4)? as usize;
+        let mut glyph_layers = Vec::with_capacity(num_layers);
+        for layer in first_layer..first_layer + num_layers {
+            let record = layer_records + 4 * layer;
+            let index = read_u16(colr, record + 2)?;
+            glyph_layers.push(ColorLayer {
+                glyph: read_u16(colr, record)?,
+                // index 0xffff stands for the text color
+                color: if index == 0xffff { None } else { palette.get(index as usize).cloned() },
+            });
+        }
+        layers.insert(glyph, glyph_layers);
+    }
+    Some(layers)
+}
+
+fn first_palette(cpal: &[u8]) -> Option<Vec<[u8; 4]>> {
+    let num_entries = read_u16(cpal, 2)? as usize;
+    let records = read_u32(cpal, 8)? as usize;
+    let first = read_u16(cpal, 12)? as usize;
+    (first..first + num_entries).map(|i| {
+        // colors are stored as BGRA
+        cpal.get(records + 4 * i..records + 4 * i + 4).map(|c| [c[2], c[1], c[0], c[3]])
+    }).collect()
+}
+
+fn sbix_strikes(sbix: &[u8]) -> Option<Vec<(u16, usize)>> {
+    let num_strikes = read_u32(sbix, 4)? as usize;
+    (0..num_strikes).map(|i| {
+        let offset = read_u32(sbix, 8 + 4 * i)? as usize;
+        Some((read_u16(sbix, offset)?, offset))
+    }).collect()
+}
+
+fn sbix_bitmap<'a>(sbix: &'a [u8], ppem: u16, strike: usize, glyph: u16) -> Option<Bitmap<'a>> {
+    let start = read_u32(sbix, strike + 4 + 4 * glyph as usize)? as usize;
+    let end = read_u32(sbix, strike + 8 + 4 * glyph as usize)? as usize;
+    let data = sbix.get(strike + start..strike + end)?;
+    if data.get(4..8)? != b"png " { return None; }
+    let png = &data[8..];
+    let (width, height) = png_size(png)?;
+    // the origin offset locates the bottom left corner of the image relative to the baseline
+    let (x, y) = (read_u16(data, 0)? as i16 as i32, read_u16(data, 2)? as i16 as i32);
+    Some(Bitmap { ppem, left: x, top: -(y + height as i32), width: width as u32, height: height as u32, png })
+}
+
+fn cblc_strikes(cblc: &[u8]) -> Option<Vec<(u16, usize)>> {
+    let num_sizes = read_u32(cblc, 4)? as usize;
+    (0..num_sizes).map(|i| {
+        let table = 8 + 48 * i;
+        Some((*cblc.get(table + 45)? as u16, table))
+    }).collect()
+}
+
+fn cbdt_bitmap<'a>(cblc: &[u8], cbdt: &'a [u8], ppem: u16, table: usize, glyph: u16) -> Option<Bitmap<'a>> {
+    let array = read_u32(cblc, table)? as usize;
+    let num_subtables = read_u32(cblc, table + 8)? as usize;
+    for i in 0..num_subtables {
+        let record = array + 8 * i;
+        let (first, last) = (read_u16(cblc, record)?, read_u16(cblc, record + 2)?);
+        if glyph < first || glyph > last { continue; }
+
+        let header = array + read_u32(cblc, record + 4)? as usize;
+        let index_format = read_u16(cblc, header)?;
+        let image_format = read_u16(cblc, header + 2)?;
+        let image_data = read_u32(cblc, header + 4)? as usize;
+        let index = (glyph - first) as usize;
+        let (start, end, shared_metrics) = match index_format {
+            1 => (read_u32(cblc, header + 8 + 4 * index)? as usize, read_u32(cblc, header + 12 + 4 * index)? as usize, None),
+            2 => {
+                let size = read_u32(cblc, header + 8)? as usize;
+                (size * index, size * (index + 1), cblc.get(header + 12..header + 20))
+            }
+            3 => (read_u16(cblc, header + 8 + 2 * index)? as usize, read_u16(cblc, header + 10 + 2 * index)? as usize, None),
+            _ => return None,
+        };
+        if end <= start { return None; }
+
+        let image = cbdt.get(image_data + start..image_data + end)?;
+        let (metrics, png) = match image_format {
+            17 => (image.get(0..5)?, image.get(9..9 + read_u32(image, 5)? as usize)?),
+            18 => (image.get(0..8)?, image.get(12..12 + read_u32(image, 8)? as usize)?),
+            19 => (shared_metrics?, image.get(4..4 + read_u32(image, 0)? as usize)?),
+            _ => return None,
+        };
+        // small and big glyph metrics both start with height, width, bearing x and bearing y
+        return Some(Bitmap {
+            ppem,
+            left: metrics[2] as i8 as i32,
+            top: -(metrics[3] as i8 as i32),
+            width: metrics[1] as u32,
+            height: metrics[0] as u32,
+            png,
+        });
+    }
+    None
+}
+
+fn layer_bounds(font: &font_rs::font::Font, layers: &[ColorLayer], scale: u32) -> Option<[i32; 4]> {
+    let mut bounds: Option<[i32; 4]> = None;
+    for layer in layers {
+        if let Some(bbox) = font.get_bbox(layer.glyph, scale) {
+            let (r, b) = (bbox.l + bbox.width(), bbox.t + bbox.height());
+            bounds = Some(match bounds {
+                Some([l, t, w, h]) => {
+                    let (l2, t2) = (l.min(bbox.l), t.min(bbox.t));
+                    [l2, t2, (l + w).max(r) - l2, (t + h).max(b) - t2]
+                }
+                None => [bbox.l, bbox.t, bbox.width(), bbox.height()],
+            });
+        }
+    }
+    bounds
+}
+
+fn unpremultiply(pixel: [f32; 4]) -> [u8; 4] {
+    if pixel[3] <= 0.0 { return [0, 0, 0, 0]; }
+    let channel = |c: f32| (c * 255.0).round().max(0.0).min(255.0) as u8;
+    [channel(pixel[0] / pixel[3]), channel(pixel[1] / pixel[3]), channel(pixel[2] / pixel[3]), channel(pixel[3])]
+}
+
+// Box filters straight alpha RGBA pixels to a new size, averaging colors weighted by alpha.
+fn resample(pixels: &[u8], width: usize, height: usize, new_width: usize, new_height: usize) -> Vec<u8> {
+    let mut resampled = Vec::with_capacity(new_width * new_height * 4);
+    for y in 0..new_height {
+        let (y0, y1) = (y * height / new_height, ((y + 1) * height + new_height - 1) / new_height);
+        for x in 0..new_width {
+            let (x0, x1) = (x * width / new_width, ((x + 1) * width + new_width - 1) / new_width);
+            let mut sum = [0.0; 4];
+            for sy in y0..y1.max(y0 + 1).min(height) {
+                for sx in x0..x1.max(x0 + 1).min(width) {
+                    let pixel = &pixels[(sy * width + sx) * 4..(sy * width + sx) * 4 + 4];
+                    let alpha = pixel[3] as f32 / 255.0;
+                    for c in 0..3 {
+                        sum[c] += pixel[c] as f32 / 255.0 * alpha;
+                    }
+                    sum[3] += alpha;
+                }
+            }
+            let count = ((y1.max(y0 + 1).min(height) - y0) * (x1.max(x0 + 1).min(width) - x0)) as f32;
+            resampled.extend_from_slice(&unpremultiply([sum[0] / count, sum[1] / count, sum[2] / count, sum[3] / count]));
+        }
+    }
+    resampled
+}
+
+#[test]
+fn test_color_layers() {
+    // COLR with one base glyph of two layers, the second drawn in the text color
+    let mut colr = vec![0, 0, 0, 1, 0, 0, 0, 14, 0, 0, 0, 20, 0, 2];
+    colr.extend_from_slice(&[0, 5, 0, 0, 0, 2]);
+    colr.extend_from_slice(&[0, 6, 0, 1, 0, 7, 0xff, 0xff]);
+    let mut cpal = vec![0, 0, 0, 2, 0, 1, 0, 2, 0, 0, 0, 14, 0, 0];
+    cpal.extend_from_slice(&[0, 0, 255, 255, 255, 0, 0, 128]);
+
+    let layers = color_layers(&colr, Some(&cpal)).unwrap();
+    let layers = &layers[&5];
+    assert_eq!(layers.len(), 2);
+    assert_eq!((layers[0].glyph, layers[0].color), (6, Some([0, 0, 255, 128])));
+    assert_eq!((layers[1].glyph, layers[1].color), (7, None));
+}
+
+#[test]
+fn test_resample() {
+    // a transparent pixel must not darken the opaque one it is averaged with
+    let pixels = [255, 255, 255, 255, 0, 0, 0, 0];
+    assert_eq!(resample(&pixels, 2, 1, 1, 1), vec![255, 255, 255, 128]);
+    let pixels = [10, 20, 30, 255];
+    assert_eq!(resample(&pixels, 1, 1, 2, 2), [10, 20, 30, 255].repeat(4));
+}
@@ -0,0 +1,450 @@
+use crate::alloc::*;
+use crate::color::*;
+
+use std::fmt;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub type FontId = usize;
+
+#[derive(Debug)]
+pub enum FontError {
+    Io(std::io::Error),
+    Invalid,
+    NotFound,
+}
+
+impl From<std::io::Error> for FontError {
+    fn from(error: std::io::Error) -> FontError {
+        FontError::Io(error)
+    }
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FontError::Io(error) => write!(f, "could not read font: {}", error),
+            FontError::Invalid => write!(f, "invalid font data"),
+            FontError::NotFound => write!(f, "no matching font found"),
+        }
+    }
+}
+
+impl std::error::Error for FontError {}
+
+// A parsed font face along with the bytes it borrows from.
+pub struct FontData {
+    // declared before `data` so that it is dropped first
+    font: font_rs::font::Font<'static>,
+    data: Arc<[u8]>,
+    family: String,
+    weight: u16,
+    italic: bool,
+    axes: Vec<Axis>,
+    hash: u64,
+    color_glyphs: ColorGlyphs,
+}
+
+impl FontData {
+    // Parses face `index` of a font or font collection.
+    pub fn new(data: Arc<[u8]>, index: usize) -> Result<FontData, FontError> {
+        let data = if is_collection(&data) {
+            Arc::from(extract_face(&data, index).ok_or(FontError::Invalid)?)
+        } else if index == 0 {
+            data
+        } else {
+            return Err(FontError::Invalid);
+        };
+        // the bytes live on the heap behind the Arc, which is kept alive alongside the font
+        let bytes: &'static [u8] = unsafe { &*(&*data as *const [u8]) };
+        let font = font_rs::font::parse(bytes).map_err(|_| FontError::Invalid)?;
+        let (family, weight, italic) = font_info(&data).ok_or(FontError::Invalid)?;
+        let axes = find_table(&data, b"fvar").and_then(variation_axes).unwrap_or_default();
+        let hash = content_hash(&data);
+        let color_glyphs = ColorGlyphs::new(&data);
+        Ok(FontData { font, data, family, weight, italic, axes, hash, color_glyphs })
+    }
+
+    pub fn font<'a>(&'a self) -> &'a font_rs::font::Font<'a> {
+        &self.font
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    // A hash of the font's contents that is stable across runs.
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    pub fn color_glyphs(&self) -> &ColorGlyphs {
+        &self.color_glyphs
+    }
+
+    pub fn family(&self) -> &str {
+        &self.family
+    }
+
+    pub fn weight(&self) -> u16 {
+        self.weight
+    }
+
+    pub fn italic(&self) -> bool {
+        self.italic
+    }
+
+    // The variation axes of a variable font, as listed in its fvar table. Setting axes is
+    // not supported: font-rs only rasterizes the default instance, and applying gvar/avar
+    // deltas would need an outline parser of our own. Faces are therefore selected by
+    // their default weight only.
+    pub fn axes(&self) -> &[Axis] {
+        &self.axes
+    }
+
+    fn weight_distance(&self, weight: u16) -> u16 {
+        (weight as i32 - self.weight as i32).abs() as u16
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Axis {
+    pub tag: [u8; 4],
+    pub min: f32,
+    pub default: f32,
+    pub max: f32,
+}
+
+// Registered font faces, grouped into families by name.
+pub struct FontRegistry {
+    fonts: Slab<FontData>,
+    families: HashMap<String, Vec<FontId>>,
+    // scanned on first use, since reading every installed font is slow
+    system_fonts: Option<Vec<FontInfo>>,
+}
+
+impl FontRegistry {
+    pub fn new() -> FontRegistry {
+        FontRegistry {
+            fonts: Slab::new(),
+            families: HashMap::new(),
+            system_fonts: None,
+        }
+    }
+
+    pub fn add(&mut self, data: FontData) -> FontId {
+        let family = data.family.to_lowercase();
+        let font = self.fonts.insert(data);
+        self.families.entry(family).or_insert_with(Vec::new).push(font);
+        font
+    }
+
+    pub fn remove(&mut self, font: FontId) -> Option<FontData> {
+        let data = self.fonts.remove(font)?;
+        let family = data.family.to_lowercase();
+        if let Some(faces) = self.families.get_mut(&family) {
+            faces.retain(|face| *face != font);
+            if faces.is_empty() { self.families.remove(&family); }
+        }
+        Some(data)
+    }
+
+    pub fn get(&self, font: FontId) -> Option<&FontData> {
+        self.fonts.get(font)
+    }
+
+    pub fn find_hash(&self, hash: u64) -> Option<FontId> {
+        self.families.values().flat_map(|faces| faces.iter()).find(|face| self.fonts.get(**face).unwrap().hash == hash).cloned()
+    }
+
+    pub fn system_fonts(&mut self) -> &[FontInfo] {
+        self.system_fonts.get_or_insert_with(system_fonts)
+    }
+
+    pub fn families<'a>(&'a self) -> impl Iterator<Item = &'a str> + 'a {
+        self.families.values().filter_map(move |faces| faces.first().map(|face| self.fonts.get(*face).unwrap().family()))
+    }
+
+    // Picks the face of the given family closest to the requested weight and style,
+    // preferring a matching style over a matching weight.
+    pub fn select(&self, family: &str, weight: u16, italic: bool) -> Option<FontId> {
+        self.families.get(&family.to_lowercase())?.iter()
+            .min_by_key(|face| {
+                let data = self.fonts.get(**face).unwrap();
+                (data.italic() != italic, data.weight_distance(weight))
+            })
+            .cloned()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FontInfo {
+    pub path: PathBuf,
+    pub index: usize,
+    pub family: String,
+    pub weight: u16,
+    pub italic: bool,
+}
+
+// 64-bit FNV-1a
+fn content_hash(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+pub fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2).map(|b| (b[0] as u16) << 8 | b[1] as u16)
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|b| (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32)
+}
+
+fn is_collection(data: &[u8]) -> bool {
+    data.get(0..4) == Some(&b"ttcf"[..])
+}
+
+pub fn face_count(data: &[u8]) -> usize {
+    if is_collection(data) {
+        read_u32(data, 8).unwrap_or(0) as usize
+    } else {
+        1
+    }
+}
+
+// Copies one face out of a font collection into a standalone font. Table offsets in a
+// collection are relative to the start of the file, so the tables are laid out again.
+fn extract_face(data: &[u8], index: usize) -> Option<Vec<u8>> {
+    if index >= face_count(data) { return None; }
+    let offset = read_u32(data, 12 + 4 * index)? as usize;
+    let num_tables = read_u16(data, offset + 4)? as usize;
+
+    let mut face = Vec::new();
+    face.extend_from_slice(data.get(offset..offset + 12)?);
+    let mut table_offset = 12 + 16 * num_tables;
+    let mut tables = Vec::new();
+    for i in 0..num_tables {
+        let record = offset + 12 + 16 * i;
+        let start = read_u32(data, record + 8)? as usize;
+        let length = read_u32(data, record + 12)? as usize;
+        face.extend_from_slice(data.get(record..record + 8)?);
+        face.extend_from_slice(&(table_offset as u32).to_be_bytes());
+        face.extend_from_slice(&(length as u32).to_be_bytes());
+        tables.push(data.get(start..start + length)?);
+        // tables are padded to four byte boundaries
+        table_offset += (length + 3) & !3;
+    }
+    for table in tables {
+        face.extend_from_slice(table);
+        face.resize((face.len() + 3) & !3, 0);
+    }
+    Some(face)
+}
+
+fn read_fixed(data: &[u8], offset: usize) -> Option<f32> {
+    read_u32(data, offset).map(|value| value as i32 as f32 / 65536.0)
+}
+
+fn variation_axes(fvar: &[u8]) -> Option<Vec<Axis>> {
+    let axes_offset = read_u16(fvar, 4)? as usize;
+    let axis_count = read_u16(fvar, 8)? as usize;
+    let axis_size = read_u16(fvar, 10)? as usize;
+    let mut axes = Vec::with_capacity(axis_count);
+    for i in 0..axis_count {
+        let record = axes_offset + axis_size * i;
+        let tag = fvar.get(record..record + 4)?;
+        axes.push(Axis {
+            tag: [tag[0], tag[1], tag[2], tag[3]],
+            min: read_fixed(fvar, record + 4)?,
+            default: read_fixed(fvar, record + 8)?,
+            max: read_fixed(fvar, record + 12)?,
+        });
+    }
+    Some(axes)
+}
+
+pub fn find_table<'a>(data: &'a [u8], tag: &[u8; 4]) -> Option<&'a [u8]> {
+    let num_tables = read_u16(data, 4)? as usize;
+    for i in 0..num_tables {
+        let record = 12 + 16 * i;
+        if data.get(record..record + 4)? == tag {
+            let offset = read_u32(data, record + 8)? as usize;
+            let length = read_u32(data, record + 12)? as usize;
+            return data.get(offset..offset + length);
+        }
+    }
+    None
+}
+
+fn family_name(name: &[u8]) -> Option<String> {
+    let count = read_u16(name, 2)? as usize;
+    let string_offset = read_u16(name, 4)? as usize;
+    // prefer the typographic family over the legacy family, and English Windows names
+    // over other Windows names over Macintosh names
+    let mut best: Option<(u32, String)> = None;
+    for i in 0..count {
+        let record = 6 + 12 * i;
+        let platform = read_u16(name, record)?;
+        let encoding = read_u16(name, record + 2)?;
+        let language = read_u16(name, record + 4)?;
+        let name_id = read_u16(name, record + 6)?;
+        let length = read_u16(name, record + 8)? as usize;
+        let offset = string_offset + read_u16(name, record + 10)? as usize;
+        let bytes = name.get(offset..offset + length)?;
+
+        let id_rank = match name_id { 16 => 0, 1 => 1, _ => continue };
+        let (platform_rank, string) = match (platform, encoding) {
+            (3, 1) | (3, 10) => {
+                let units: Vec<u16> = bytes.chunks(2).filter(|c| c.len() == 2).map(|c| (c[0] as u16) << 8 | c[1] as u16).collect();
+                (if language == 0x0409 { 0 } else { 1 }, String::from_utf16_lossy(&units))
+            }
+            (1, 0) => (2, bytes.iter().map(|&b| b as char).collect()),
+            _ => continue,
+        };
+        let rank = id_rank * 3 + platform_rank;
+        if best.as_ref().map_or(true, |(best_rank, _)| rank < *best_rank) {
+            best = Some((rank, string));
+        }
+    }
+    best.map(|(_, string)| string)
+}
+
+// Reads the family name, weight class and italic flag of a font.
+pub fn font_info(data: &[u8]) -> Option<(String, u16, bool)> {
+    let family = family_name(find_table(data, b"name")?)?;
+    if let Some(os2) = find_table(data, b"OS/2") {
+        let weight = read_u16(os2, 4)?;
+        let selection = read_u16(os2, 62)?;
+        // bit 0 is italic, bit 9 is oblique
+        Some((family, weight, selection & 0x0201 != 0))
+    } else {
+        let style = read_u16(find_table(data, b"head")?, 44)?;
+        Some((family, if style & 1 != 0 { 700 } else { 400 }, style & 2 != 0))
+    }
+}
+
+// Picks the face of the given family closest to the requested weight and style,
+// preferring a matching style over a matching weight.
+pub fn match_font<'a>(fonts: &'a [FontInfo], family: &str, weight: u16, italic: bool) -> Option<&'a FontInfo> {
+    fonts.iter()
+        .filter(|info| info.family.eq_ignore_ascii_case(family))
+        .min_by_key(|info| (info.italic != italic, (info.weight as i32 - weight as i32).abs()))
+}
+
+#[cfg(target_os = "linux")]
+pub fn system_font_dirs() -> Vec<PathBuf> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let data_home = std::env::var_os("XDG_DATA_HOME").map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".local/share")));
+
+    let mut dirs = Vec::new();
+    // fontconfig lists its directories as <dir> elements, optionally with prefix="xdg"
+    if let Ok(config) = std::fs::read_to_string("/etc/fonts/fonts.conf") {
+        let mut rest = &config[..];
+        while let Some(start) = rest.find("<dir") {
+            rest = &rest[start + 4..];
+            let (attributes, body) = match (rest.find('>'), rest.find("</dir>")) {
+                (Some(close), Some(end)) if close < end => (&rest[..close], &rest[close + 1..end]),
+                _ => continue,
+            };
+            let path = body.trim();
+            if attributes.contains("prefix=\"xdg\"") {
+                if let Some(data_home) = &data_home { dirs.push(data_home.join(path)); }
+            } else if path.starts_with("~/") {
+                if let Some(home) = &home { dirs.push(home.join(&path[2..])); }
+            } else {
+                dirs.push(PathBuf::from(path));
+            }
+        }
+    }
+    if dirs.is_empty() {
+        dirs.push(PathBuf::from("/usr/share/fonts"));
+        dirs.push(PathBuf::from("/usr/local/share/fonts"));
+        if let Some(data_home) = &data_home { dirs.push(data_home.join("fonts")); }
+        if let Some(home) = &home { dirs.push(home.join(".fonts")); }
+    }
+    dirs
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn system_font_dirs() -> Vec<PathBuf> {
+    Vec::new()
+}
+
+pub fn system_fonts() -> Vec<FontInfo> {
+    let mut fonts = Vec::new();
+    for dir in system_font_dirs() {
+        scan_dir(&dir, &mut fonts);
+    }
+    fonts
+}
+
+fn scan_dir(dir: &Path, fonts: &mut Vec<FontInfo>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        if path.is_dir() {
+            scan_dir(&path, fonts);
+            continue;
+        }
+        let extension = path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_ascii_lowercase());
+        if let Some("ttf") | Some("otf") | Some("ttc") | Some("otc") = extension.as_ref().map(|ext| &ext[..]) {
+            if let Ok(data) = std::fs::read(&path) {
+                for index in 0..face_count(&data) {
+                    let face = if is_collection(&data) { extract_face(&data, index) } else { Some(data.clone()) };
+                    if let Some((family, weight, italic)) = face.as_ref().and_then(|face| font_info(face)) {
+                        fonts.push(FontInfo { path: path.clone(), index, family, weight, italic });
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_font_info() {
+    let (family, weight, italic) = font_info(include_bytes!("../res/sawarabi-gothic-medium.ttf")).unwrap();
+    assert_eq!(family, "Sawarabi Gothic");
+    assert_eq!(weight, 500);
+    assert!(!italic);
+}
+
+#[test]
+fn test_match_font() {
+    let info = |weight, italic| FontInfo { path: PathBuf::new(), index: 0, family: "Sans".to_string(), weight, italic };
+    let fonts = [info(300, false), info(400, false), info(700, false), info(400, true)];
+    assert_eq!(match_font(&fonts, "sans", 600, false).unwrap().weight, 700);
+    assert!(match_font(&fonts, "Sans", 700, true).unwrap().italic);
+    assert!(match_font(&fonts, "Serif", 400, false).is_none());
+}
+
+#[test]
+fn test_extract_face() {
+    let font = include_bytes!("../res/sawarabi-gothic-medium.ttf");
+    // wrap the font in a single face collection
+    let mut collection = Vec::new();
+    collection.extend_from_slice(b"ttcf");
+    collection.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 1, 0, 0, 0, 16]);
+    let num_tables = read_u16(font, 4).unwrap() as usize;
+    collection.extend_from_slice(&font[..12 + 16 * num_tables]);
+    for i in 0..num_tables {
+        let record = 16 + 12 + 16 * i;
+        let offset = read_u32(&collection, record + 8).unwrap() + 16;
+        collection[record + 8..record + 12].copy_from_slice(&offset.to_be_bytes());
+    }
+    collection.extend_from_slice(&font[12 + 16 * num_tables..]);
+
+    assert_eq!(face_count(&collection), 1);
+    let face = extract_face(&collection, 0).unwrap();
+    assert_eq!(find_table(&face, b"OS/2"), find_table(font, b"OS/2"));
+    assert_eq!(font_info(&face).unwrap().0, "Sawarabi Gothic");
+    assert!(extract_face(&collection, 1).is_none());
+}
@@ -0,0 +1,96 @@
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8; 4] = b"JIGC";
+const VERSION: u32 = 1;
+
+// A rasterized glyph as stored on disk. Fonts are identified by a hash of their contents.
+pub struct CachedGlyph {
+    pub mode: u8,
+    pub font: u64,
+    pub scale: u32,
+    pub glyph: u16,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+pub fn write_glyph_cache<W: Write>(writer: &mut W, glyphs: &[CachedGlyph]) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&(glyphs.len() as u32).to_le_bytes())?;
+    for glyph in glyphs {
+        writer.write_all(&[glyph.mode])?;
+        writer.write_all(&glyph.font.to_le_bytes())?;
+        writer.write_all(&glyph.scale.to_le_bytes())?;
+        writer.write_all(&glyph.glyph.to_le_bytes())?;
+        writer.write_all(&glyph.width.to_le_bytes())?;
+        writer.write_all(&glyph.height.to_le_bytes())?;
+        writer.write_all(&(glyph.data.len() as u32).to_le_bytes())?;
+        writer.write_all(&glyph.data)?;
+    }
+    writer.flush()
+}
+
+pub fn read_glyph_cache<R: Read>(reader: &mut R) -> io::Result<Vec<CachedGlyph>> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC || read_u32(reader)? != VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a glyph cache file"));
+    }
+    let count = read_u32(reader)?;
+    let mut glyphs = Vec::new();
+    for _ in 0..count {
+        let mut mode = [0; 1];
+        reader.read_exact(&mut mode)?;
+        let font = read_u64(reader)?;
+        let scale = read_u32(reader)?;
+        let glyph = read_u16(reader)?;
+        let width = read_u32(reader)?;
+        let height = read_u32(reader)?;
+        let len = read_u32(reader)? as usize;
+        let mut data = Vec::new();
+        reader.take(len as u64).read_to_end(&mut data)?;
+        if data.len() != len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated glyph cache file"));
+        }
+        glyphs.push(CachedGlyph { mode: mode[0], font, scale, glyph, width, height, data });
+    }
+    Ok(glyphs)
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[test]
+fn test_roundtrip() {
+    let glyphs = [
+        CachedGlyph { mode: 0, font: 0x0123456789abcdef, scale: 14, glyph: 42, width: 2, height: 3, data: vec![1, 2, 3, 4, 5, 6] },
+        CachedGlyph { mode: 1, font: 7, scale: 20, glyph: 3, width: 1, height: 1, data: vec![7, 8, 9] },
+    ];
+    let mut bytes = Vec::new();
+    write_glyph_cache(&mut bytes, &glyphs).unwrap();
+    let read = read_glyph_cache(&mut &bytes[..]).unwrap();
+    assert_eq!(read.len(), 2);
+    assert_eq!((read[0].font, read[0].scale, read[0].glyph), (0x0123456789abcdef, 14, 42));
+    assert_eq!(read[0].data, glyphs[0].data);
+    assert_eq!((read[1].mode, read[1].width, read[1].height), (1, 1, 1));
+    assert_eq!(read[1].data, glyphs[1].data);
+
+    assert!(read_glyph_cache(&mut &bytes[..bytes.len() - 1]).is_err());
+    assert!(read_glyph_cache(&mut &b"nope"[..]).is_err());
+}
@@ -1,207 +1,856 @@
 use crate::render::*;
 use crate::alloc::*;
+use crate::font::*;
+use crate::sdf::*;
+use crate::subpixel::*;
+use crate::glyph_cache::*;
+use crate::alloc::Slab;
 
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
 use std::f32::consts::PI;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 const TOLERANCE: f32 = 0.1;
+const ATLAS_SIZE: u32 = 1024;
+const GLYPH_BORDER: u32 = 1;
+const SDF_SCALE: u32 = 32;
+const SDF_SPREAD: u32 = 4;
+const MAX_DAMAGE_RECTS: usize = 8;
+const PATH_CACHE_FRAMES: usize = 60;
 
 pub struct Graphics {
     dpi_factor: f32,
     renderer: Renderer,
-    fonts: Slab<font_rs::font::Font<'static>>,
-    atlas: Atlas,
-    atlas_tex: TexId,
+    fonts: FontRegistry,
+    atlas: GlyphAtlas,
+    lcd_atlas: GlyphAtlas,
+    sdf_atlas: GlyphAtlas,
+    color_atlas: GlyphAtlas,
+    glyph_mode: GlyphMode,
+    path_mode: PathMode,
+    blend_mode: BlendMode,
+    atlas_overlay: bool,
+    effects: Slab<Effect>,
+    path_cache: PathCache,
+    clear_color: Option<Color>,
+
+    // the previous frame's display list, for damage tracking
+    last_frame: Vec<(u64, Bounds)>,
+    last_size: [f32; 2],
+    last_clear: Option<Color>,
+    invalidated: bool,
+    damage: Vec<Bounds>,
+    partial_redraw: bool,
+    samples: u32,
+    redraw_target: Option<(RenderTargetId, usize, usize, u32)>,
+    frame_stats: FrameStats,
 
     layers: Vec<(usize, usize)>,
     stack: Vec<usize>,
     items: Vec<DisplayItem>,
+    item_bounds: Vec<Bounds>,
     glyphs: Vec<Glyph>,
     paths: Vec<PathSegment>,
+    shapes: Vec<Shape>,
+    effect_quads: Vec<EffectQuad>,
 }
 
 impl Graphics {
-    pub fn new(dpi_factor: f32) -> Graphics {
-        let mut renderer = Renderer::new();
-        let atlas_tex = renderer.create_tex(TexFormat::A, 1024, 1024, &[0; 1024*1024]);
+    pub fn new(dpi_factor: f32) -> Result<Graphics, RendererError> {
+        let mut renderer = Renderer::new()?;
+        // the atlases that save_glyph_cache writes out keep copies of their bitmaps
+        let atlas = GlyphAtlas::new(&mut renderer, TexFormat::A, true)?;
+        let lcd_atlas = GlyphAtlas::new(&mut renderer, TexFormat::LCD, true)?;
+        // without the SDF shader, the atlas holds distance fields already evaluated into coverage
+        let sdf_format = if renderer.supports_distance_field() { TexFormat::SDF } else { TexFormat::A };
+        let sdf_atlas = GlyphAtlas::new(&mut renderer, sdf_format, sdf_format == TexFormat::SDF)?;
+        // palette colors, the text color and embedded PNGs are all sRGB encoded
+        let color_atlas = GlyphAtlas::new(&mut renderer, TexFormat::SRGBA, false)?;
+        Ok(Graphics::with_atlases(dpi_factor, renderer, [atlas, lcd_atlas, sdf_atlas, color_atlas]))
+    }
+
+    fn with_atlases(dpi_factor: f32, renderer: Renderer, atlases: [GlyphAtlas; 4]) -> Graphics {
+        let [atlas, lcd_atlas, sdf_atlas, color_atlas] = atlases;
         Graphics {
             dpi_factor,
             renderer,
-            fonts: Slab::new(),
-            atlas: Atlas::new(1024, 1024),
-            atlas_tex,
+            fonts: FontRegistry::new(),
+            atlas,
+            lcd_atlas,
+            sdf_atlas,
+            color_atlas,
+            glyph_mode: GlyphMode::Bitmap,
+            path_mode: PathMode::Fringe,
+            blend_mode: BlendMode::Normal,
+            atlas_overlay: false,
+            effects: Slab::new(),
+            path_cache: PathCache::new(),
+            clear_color: None,
+
+            last_frame: Vec::new(),
+            last_size: [0.0, 0.0],
+            last_clear: None,
+            invalidated: true,
+            damage: Vec::new(),
+            partial_redraw: false,
+            samples: 1,
+            redraw_target: None,
+            frame_stats: FrameStats::default(),
 
             layers: Vec::new(),
             stack: Vec::new(),
             items: Vec::new(),
+            item_bounds: Vec::new(),
             glyphs: Vec::new(),
             paths: Vec::new(),
+            shapes: Vec::new(),
+            effect_quads: Vec::new(),
         }
     }
 
-    pub fn add_font(&mut self, bytes: &'static [u8]) -> FontId {
-        self.fonts.insert(font_rs::font::parse(bytes).unwrap())
+    // Graphics without an OpenGL context or glyph atlas pages, for testing what is built on
+    // top of it without drawing.
+    #[cfg(test)]
+    pub fn headless() -> Graphics {
+        let atlas = |format| GlyphAtlas { format, pages: Vec::new(), bitmaps: None, uploads: 0, upload_time: Duration::default() };
+        let atlases = [atlas(TexFormat::A), atlas(TexFormat::LCD), atlas(TexFormat::SDF), atlas(TexFormat::SRGBA)];
+        Graphics::with_atlases(1.0, Renderer::headless(), atlases)
+    }
+
+    pub fn add_font<B: Into<Arc<[u8]>>>(&mut self, bytes: B) -> Result<FontId, FontError> {
+        Ok(self.fonts.add(FontData::new(bytes.into(), 0)?))
+    }
+
+    pub fn add_font_collection<B: Into<Arc<[u8]>>>(&mut self, bytes: B) -> Result<Vec<FontId>, FontError> {
+        let bytes = bytes.into();
+        let faces = (0..face_count(&bytes))
+            .map(|index| FontData::new(bytes.clone(), index))
+            .collect::<Result<Vec<FontData>, FontError>>()?;
+        Ok(faces.into_iter().map(|face| self.fonts.add(face)).collect())
+    }
+
+    pub fn add_font_file<P: AsRef<Path>>(&mut self, path: P) -> Result<FontId, FontError> {
+        self.add_font(std::fs::read(path)?)
+    }
+
+    pub fn add_system_font(&mut self, family: &str, weight: u16, italic: bool) -> Result<FontId, FontError> {
+        let info = match_font(self.fonts.system_fonts(), family, weight, italic).ok_or(FontError::NotFound)?.clone();
+        let bytes = std::fs::read(&info.path)?;
+        Ok(self.fonts.add(FontData::new(bytes.into(), info.index)?))
     }
 
+    // Also drops the font's glyphs from the atlases, as its id may be reused by a later font.
     pub fn remove_font(&mut self, font: FontId) {
         self.fonts.remove(font);
+        for atlas in [&mut self.atlas, &mut self.lcd_atlas, &mut self.sdf_atlas, &mut self.color_atlas].iter_mut() {
+            atlas.remove_font(font);
+        }
+    }
+
+    pub fn select_font(&self, family: &str, weight: u16, italic: bool) -> Option<FontId> {
+        self.fonts.select(family, weight, italic)
+    }
+
+    // Read only, see FontData::axes.
+    pub fn font_axes(&self, font: FontId) -> &[Axis] {
+        self.fonts.get(font).unwrap().axes()
+    }
+
+    pub fn atlas_stats(&self) -> AtlasStats {
+        let mut stats = AtlasStats::default();
+        let mut used_area = 0;
+        for atlas in [&self.atlas, &self.lcd_atlas, &self.sdf_atlas, &self.color_atlas].iter() {
+            stats.pages += atlas.pages.len();
+            stats.uploads += atlas.uploads;
+            for page in atlas.pages.iter() {
+                stats.rows += page.atlas.rows.iter().count();
+                stats.evictions += page.atlas.evictions;
+                used_area += page.atlas.used_area();
+            }
+        }
+        stats.occupancy = used_area as f32 / (stats.pages as f32 * ATLAS_SIZE as f32 * ATLAS_SIZE as f32);
+        stats
+    }
+
+    // Draws every atlas page along the top of the window, with row boundaries marked.
+    pub fn atlas_overlay(&mut self, overlay: bool) {
+        self.atlas_overlay = overlay;
+    }
+
+    pub fn glyph_mode(&mut self, mode: GlyphMode) {
+        self.glyph_mode = mode;
     }
 
+    // Applies to fills recorded afterwards.
+    pub fn path_mode(&mut self, mode: PathMode) {
+        self.path_mode = mode;
+    }
+
+    // Applies to everything drawn afterwards. Consecutive items with the same blend mode are
+    // batched together, and items are never reordered across a change of blend mode.
+    pub fn blend_mode(&mut self, mode: BlendMode) {
+        self.blend_mode = mode;
+    }
+
+    pub fn text_gamma(&mut self, gamma: f32, contrast: f32) {
+        self.renderer.text_gamma(gamma, contrast);
+        self.invalidated = true;
+    }
+
+    // Keeps the drawn frame in a render target and redraws only the damaged regions of it,
+    // which are then copied to the bound framebuffer. Not available on OpenGL ES 2, which
+    // always redraws everything.
+    pub fn partial_redraw(&mut self, partial_redraw: bool) {
+        self.partial_redraw = partial_redraw;
+        self.invalidated = true;
+    }
+
+    // Draws into a render target with the given number of samples per pixel, usually 4 or 8,
+    // which is resolved into the bound framebuffer. Paths are then drawn without the fringe,
+    // so that arbitrary paths get correct antialiasing. 1 turns it off. Not available on
+    // OpenGL ES 2.
+    pub fn msaa(&mut self, samples: u32) {
+        self.samples = samples.max(1);
+        self.invalidated = true;
+    }
+
+    // Marks the whole window as damaged, for when its contents were lost.
+    pub fn invalidate(&mut self) {
+        self.invalidated = true;
+    }
+
+    // Timings and counts of the last call to draw. Layout and render are left for UI::run.
+    pub fn frame_stats(&self) -> FrameStats {
+        self.frame_stats
+    }
+
+    // Regions that changed between the last two frames drawn, in pixels.
+    pub fn damage(&self) -> &[Bounds] {
+        &self.damage
+    }
+
+    // For render targets and readback. Graphics::draw draws into whichever framebuffer is
+    // bound, sized by the width and height it is given.
+    pub fn renderer(&mut self) -> &mut Renderer {
+        &mut self.renderer
+    }
+
+    // Deferred like everything else recorded: the frame is cleared to the color at the start
+    // of the next draw, beneath all items, wherever in the frame this is called. Without a
+    // clear color, whatever is redrawn is cleared to transparent.
     pub fn clear(&mut self, color: Color) {
-        self.renderer.clear(color.to_linear());
+        self.clear_color = Some(color);
     }
 
-    pub fn draw(&mut self, width: f32, height: f32) {
+    // Returns whether anything changed since the last frame, and so whether the frame needs
+    // to be presented. With partial redraw nothing is drawn at all when nothing changed.
+    pub fn draw(&mut self, width: f32, height: f32) -> bool {
+        self.frame_stats = FrameStats::default();
+        self.renderer.reset_counts();
+
+        let frame: Vec<(u64, Bounds)> = self.items.iter().zip(self.item_bounds.iter())
+            .map(|(item, bounds)| (self.item_hash(item), *bounds))
+            .collect();
+        let screen = Bounds { x: 0.0, y: 0.0, width, height };
+        let full = self.invalidated || self.atlas_overlay || self.last_size != [width, height] || self.last_clear != self.clear_color;
+        self.damage = if full { vec![screen] } else { damage(&self.last_frame, &frame, screen) };
+        self.last_frame = frame;
+        self.last_size = [width, height];
+        self.last_clear = self.clear_color;
+        self.invalidated = false;
+        let changed = !self.damage.is_empty();
+
+        let previous_target = self.renderer.render_target();
+        let offscreen = (self.partial_redraw && changed) || (!self.partial_redraw && self.samples > 1);
+        let target = if offscreen { self.bind_redraw_target(width, height) } else { None };
+        let partial = target.is_some() && self.partial_redraw;
+        let drawing = target.is_some() || !self.partial_redraw;
+        #[cfg(feature = "gpu-timer")]
+        {
+            if drawing { self.renderer.begin_gpu_timer(); }
+        }
+        if drawing {
+            for atlas in [&mut self.atlas, &mut self.lcd_atlas, &mut self.sdf_atlas, &mut self.color_atlas].iter_mut() {
+                atlas.update_counter();
+            }
+            self.path_cache.update_counter();
+
+            let regions = if partial { self.damage.clone() } else { vec![screen] };
+            for region in regions {
+                if partial {
+                    let (x, y) = (region.x.floor(), region.y.floor());
+                    let (x2, y2) = ((region.x + region.width).ceil(), (region.y + region.height).ceil());
+                    self.renderer.scissor(Some([x as i32, y as i32, (x2 - x) as i32, (y2 - y) as i32]));
+                }
+                // redrawn regions must not keep what was drawn there before
+                self.renderer.clear(self.clear_color.map_or([0.0; 4], |color| color.to_linear()));
+
+                // items outside of the region would be discarded by the scissor test anyway
+                let visible: Vec<usize> = (0..self.items.len())
+                    .filter(|&i| !partial || self.item_bounds[i].intersects(&region))
+                    .collect();
+                // draw_run reorders the items it is given, so runs end wherever that would
+                // change what is drawn on top
+                let mut start = 0;
+                while start < visible.len() {
+                    let batch = self.items[visible[start]].batch();
+                    let end = visible[start..].iter()
+                        .position(|&i| self.items[i].batch() != batch)
+                        .map_or(visible.len(), |len| start + len);
+                    self.draw_run(&visible[start..end], batch.0, width, height);
+                    start = end;
+                }
+            }
+            self.renderer.scissor(None);
+            self.renderer.blend_mode(BlendMode::Normal);
+
+            if self.atlas_overlay {
+                self.draw_atlas_overlay(width, height);
+            }
+        }
+        if let Some(target) = target {
+            let _ = self.renderer.bind_render_target(previous_target);
+            let _ = self.renderer.blit_render_target(target);
+        }
+        #[cfg(feature = "gpu-timer")]
+        {
+            if drawing { self.frame_stats.gpu = self.renderer.end_gpu_timer(); }
+        }
+
+        let stats = &mut self.frame_stats;
+        stats.changed = changed;
+        if drawing {
+            stats.atlas_upload = self.atlas.upload_time + self.lcd_atlas.upload_time + self.sdf_atlas.upload_time + self.color_atlas.upload_time;
+        }
+        stats.submit = self.renderer.submit_time();
+        stats.vertices = self.renderer.vertices();
+        stats.draw_calls = self.renderer.draw_calls();
+
+        self.clear_color = None;
+        self.layers = Vec::new();
+        self.stack = Vec::new();
+        self.items = Vec::new();
+        self.item_bounds = Vec::new();
+        self.glyphs = Vec::new();
+        self.paths = Vec::new();
+        self.shapes = Vec::new();
+        self.effect_quads = Vec::new();
+
+        changed
+    }
+
+    // Binds the render target kept for partial redraw and multisampling, recreating it at a
+    // new size or sample count. When it can't be used, both are turned off and the frame is
+    // drawn in full instead.
+    fn bind_redraw_target(&mut self, width: f32, height: f32) -> Option<RenderTargetId> {
+        let (width, height) = (width.ceil() as usize, height.ceil() as usize);
+        if let Some((target, target_width, target_height, samples)) = self.redraw_target {
+            if (target_width, target_height, samples) != (width, height, self.samples) {
+                self.renderer.delete_render_target(target);
+                self.redraw_target = None;
+            }
+        }
+        if self.redraw_target.is_none() && self.renderer.api() != GlApi::Gles2 {
+            let target = if self.samples > 1 {
+                self.renderer.create_render_target_multisampled(width, height, self.samples)
+            } else {
+                self.renderer.create_render_target(width, height)
+            };
+            self.redraw_target = target.ok().map(|target| (target, width, height, self.samples));
+        }
+        match self.redraw_target {
+            Some((target, ..)) if self.renderer.bind_render_target(Some(target)).is_ok() => Some(target),
+            _ => {
+                self.partial_redraw = false;
+                self.samples = 1;
+                self.damage = vec![Bounds { x: 0.0, y: 0.0, width: width as f32, height: height as f32 }];
+                None
+            }
+        }
+    }
+
+    fn item_hash(&self, item: &DisplayItem) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        match *item {
+            DisplayItem::Glyphs(color, blend, mode, start, end) => {
+                (0u8, blend, mode).hash(&mut hasher);
+                hash_floats(&mut hasher, &[color.r, color.g, color.b, color.a]);
+                for glyph in self.glyphs[start..end].iter() {
+                    glyph.id.hash(&mut hasher);
+                    hash_floats(&mut hasher, &[glyph.pos[0], glyph.pos[1], glyph.scale]);
+                }
+            }
+            DisplayItem::ColorGlyphs(color, blend, start, end) => {
+                (1u8, blend).hash(&mut hasher);
+                hash_floats(&mut hasher, &[color.r, color.g, color.b, color.a]);
+                for glyph in self.glyphs[start..end].iter() {
+                    glyph.id.hash(&mut hasher);
+                    hash_floats(&mut hasher, &[glyph.pos[0], glyph.pos[1], glyph.scale]);
+                }
+            }
+            DisplayItem::FillPath(color, blend, start, end) => {
+                (2u8, blend).hash(&mut hasher);
+                hash_floats(&mut hasher, &[color.r, color.g, color.b, color.a]);
+                for PathSegment(pos, segment) in self.paths[start..end].iter() {
+                    hash_floats(&mut hasher, pos);
+                    match *segment {
+                        SegmentType::Line => 0u8.hash(&mut hasher),
+                        SegmentType::Arc(radius, start_angle, end_angle) => {
+                            1u8.hash(&mut hasher);
+                            hash_floats(&mut hasher, &[radius, start_angle, end_angle]);
+                        }
+                    }
+                }
+            }
+            DisplayItem::Shape(color, blend, index) => {
+                let Shape { pos, size, radius } = self.shapes[index];
+                (4u8, blend).hash(&mut hasher);
+                hash_floats(&mut hasher, &[color.r, color.g, color.b, color.a, pos[0], pos[1], size[0], size[1], radius]);
+            }
+            DisplayItem::StencilPath(color, blend, start, end) => {
+                (5u8, blend).hash(&mut hasher);
+                hash_floats(&mut hasher, &[color.r, color.g, color.b, color.a]);
+                for PathSegment(pos, _) in self.paths[start..end].iter() {
+                    hash_floats(&mut hasher, pos);
+                }
+            }
+            DisplayItem::Custom(blend, index) => {
+                let quad = &self.effect_quads[index];
+                (3u8, blend, quad.effect).hash(&mut hasher);
+                hash_floats(&mut hasher, &[quad.pos[0], quad.pos[1], quad.size[0], quad.size[1]]);
+                for (name, value) in quad.uniforms.iter() {
+                    name.hash(&mut hasher);
+                    match *value {
+                        Uniform::Float(x) => hash_floats(&mut hasher, &[x]),
+                        Uniform::Vec2(v) => hash_floats(&mut hasher, &v),
+                        Uniform::Vec3(v) => hash_floats(&mut hasher, &v),
+                        Uniform::Vec4(v) => hash_floats(&mut hasher, &v),
+                    }
+                }
+            }
+        }
+        hasher.finish()
+    }
+
+    fn push_item(&mut self, item: DisplayItem, bounds: Bounds) {
+        self.items.push(item);
+        self.item_bounds.push(bounds);
+    }
+
+    // Draws display items of the same kind and blend mode, batched by atlas page.
+    fn draw_run(&mut self, items: &[usize], blend: BlendMode, width: f32, height: f32) {
+        self.renderer.blend_mode(blend);
+
         let mut glyphs = Vec::new();
         let mut paths = Vec::new();
+        let mut shapes = Vec::new();
+        let mut stencil_paths = Vec::new();
+        let mut effect_quads = Vec::new();
 
-        for item in self.items.iter() {
-            match item {
-                DisplayItem::Glyphs(color, start, end) => {
-                    glyphs.push((color, &self.glyphs[*start..*end]));
+        for &i in items.iter() {
+            match &self.items[i] {
+                DisplayItem::Glyphs(color, _, mode, start, end) => {
+                    glyphs.push((color, Some(*mode), &self.glyphs[*start..*end]));
+                }
+                DisplayItem::ColorGlyphs(color, _, start, end) => {
+                    glyphs.push((color, None, &self.glyphs[*start..*end]));
                 }
-                DisplayItem::FillPath(color, start, end) => {
+                DisplayItem::FillPath(color, _, start, end) => {
                     paths.push((color, &self.paths[*start..*end]));
                 }
+                DisplayItem::Shape(color, _, index) => {
+                    shapes.push((color, self.shapes[*index]));
+                }
+                DisplayItem::StencilPath(color, _, start, end) => {
+                    stencil_paths.push((color, &self.paths[*start..*end]));
+                }
+                DisplayItem::Custom(_, index) => {
+                    effect_quads.push(&self.effect_quads[*index]);
+                }
             }
         }
 
         let mut path_verts: Vec<Vertex> = Vec::new();
         let mut path_indices: Vec<u16> = Vec::new();
         for (color, path) in paths {
-            let col = color.to_linear();
-            let mut col_alpha = col;
-            col_alpha[3] = 0.0;
-
-            let mut verts = Vec::new();
-            for (i, PathSegment(pos, segment)) in path.iter().enumerate() {
-                match segment {
-                    SegmentType::Line => {
-                        verts.push(*pos);
+            let col = color.vertex_color(blend);
+            let start = Instant::now();
+            let (origin, tessellation) = self.path_cache.get(path);
+            self.frame_stats.tessellation += start.elapsed();
+            if path_verts.len() + tessellation.verts.len() > u16::MAX as usize {
+                self.renderer.draw(&path_verts, &path_indices);
+                path_verts.clear();
+                path_indices.clear();
+            }
+            let path_start = path_verts.len() as u16;
+            if self.samples > 1 {
+                // the fringe collapses onto the path's edge, halfway between its inner and
+                // outer vertices, and multisampling antialiases that edge instead
+                for pair in tessellation.verts.chunks(2) {
+                    let (inner, outer) = (pair[0].0, pair[1].0);
+                    let pos = [(inner[0] + outer[0]) * 0.5, (inner[1] + outer[1]) * 0.5];
+                    let (x, y) = pixel_to_ndc(origin[0] + pos[0], origin[1] + pos[1], width, height);
+                    path_verts.push(Vertex { pos: [x, y, 0.0], col });
+                    path_verts.push(Vertex { pos: [x, y, 0.0], col });
+                }
+            } else {
+                for &(pos, outer) in tessellation.verts.iter() {
+                    let (x, y) = pixel_to_ndc(origin[0] + pos[0], origin[1] + pos[1], width, height);
+                    path_verts.push(Vertex { pos: [x, y, 0.0], col: if outer { [0.0; 4] } else { col } });
+                }
+            }
+            path_indices.extend(tessellation.indices.iter().map(|i| path_start + i));
+        }
+        self.renderer.draw(&path_verts, &path_indices);
+
+        let mut shape_verts: Vec<VertexShape> = Vec::new();
+        let mut shape_indices: Vec<u16> = Vec::new();
+        for (color, Shape { pos, size, radius }) in shapes {
+            if shape_verts.len() + 4 > u16::MAX as usize {
+                self.renderer.draw_shapes(&shape_verts, &shape_indices);
+                shape_verts.clear();
+                shape_indices.clear();
+            }
+            // the quad extends a pixel past the shape to leave room for its antialiased edge
+            let col = color.vertex_color(blend);
+            let half_size = [0.5 * size[0], 0.5 * size[1]];
+            let shape = [half_size[0], half_size[1], radius];
+            let (x1, y1) = pixel_to_ndc(pos[0] - 1.0, pos[1] - 1.0, width, height);
+            let (x2, y2) = pixel_to_ndc(pos[0] + size[0] + 1.0, pos[1] + size[1] + 1.0, width, height);
+            let (u, v) = (half_size[0] + 1.0, half_size[1] + 1.0);
+            let i = shape_verts.len() as u16;
+            shape_verts.extend_from_slice(&[
+                VertexShape { pos: [x1, y1, 0.0], col, uv: [-u, -v], shape },
+                VertexShape { pos: [x2, y1, 0.0], col, uv: [u, -v], shape },
+                VertexShape { pos: [x2, y2, 0.0], col, uv: [u, v], shape },
+                VertexShape { pos: [x1, y2, 0.0], col, uv: [-u, v], shape },
+            ]);
+            shape_indices.extend_from_slice(&[i, i+1, i+2, i, i+2, i+3]);
+        }
+        self.renderer.draw_shapes(&shape_verts, &shape_indices);
+
+        for (color, path) in stencil_paths {
+            let col = color.vertex_color(blend);
+            let start = Instant::now();
+            let points = flatten(path);
+            let verts: Vec<Vertex> = points.iter().map(|point| {
+                let (x, y) = pixel_to_ndc(point[0], point[1], width, height);
+                Vertex { pos: [x, y, 0.0], col }
+            }).collect();
+            let mut indices = Vec::new();
+            for i in 1..points.len().saturating_sub(1) {
+                indices.extend_from_slice(&[0, i as u16, i as u16 + 1]);
+            }
+            // multisampling antialiases the edges already
+            let (fringe, fringe_indices) = if self.samples > 1 { (Vec::new(), Vec::new()) } else { edge_fringe(&points, col, width, height) };
+            self.frame_stats.tessellation += start.elapsed();
+            let bounds = path_bounds(path);
+            let mut cover = Vec::new();
+            let mut cover_indices = Vec::new();
+            rect_quad(&mut cover, &mut cover_indices, [bounds.x, bounds.y], [bounds.width, bounds.height], col, width, height);
+            self.renderer.fill_stencil(&verts, &indices, &fringe, &fringe_indices, &cover, &cover_indices);
+        }
+
+        // color glyphs are listed with no glyph mode
+        for &mode in [Some(GlyphMode::Bitmap), Some(GlyphMode::Subpixel), Some(GlyphMode::DistanceField), None].iter() {
+            let atlas = match mode {
+                Some(GlyphMode::Bitmap) => &mut self.atlas,
+                Some(GlyphMode::Subpixel) => &mut self.lcd_atlas,
+                Some(GlyphMode::DistanceField) => &mut self.sdf_atlas,
+                None => &mut self.color_atlas,
+            };
+
+            // one batch of vertices and indices per atlas page
+            let mut batches: Vec<(Vec<VertexUV>, Vec<u16>)> = Vec::new();
+
+            for (color, glyph_mode, glyph_list) in glyphs.iter() {
+                if *glyph_mode != mode { continue; }
+                let col = match mode {
+                    Some(_) => color.vertex_color(blend),
+                    // color glyphs keep their own colors and only take the alpha of the text color
+                    None => [color.a; 4],
+                };
+                for glyph in glyph_list.iter() {
+                    let font = self.fonts.get(glyph.id.font).unwrap();
+                    let cpu_sdf = !self.renderer.supports_distance_field();
+                    let entry = atlas.get_or_insert(&mut self.renderer, glyph.id, || match mode {
+                        Some(mode) => Some(rasterize(font.font(), glyph.id, mode, cpu_sdf)),
+                        None => rasterize_color(font, glyph.id),
+                    });
+                    let (page, rect) = if let Some(entry) = entry {
+                        entry
+                    } else {
+                        // the glyph is larger than an entire page
+                        continue;
+                    };
+
+                    if batches.len() <= page {
+                        batches.resize_with(page + 1, || (Vec::new(), Vec::new()));
                     }
-                    SegmentType::Arc(radius, start_angle, end_angle) => {
-                        let PathSegment(next, _) = path[(i+1) % path.len()];
-                        let segments: u16 = (((end_angle - start_angle).abs() / (1.0 - TOLERANCE / radius).acos()).ceil() as u16).max(4);
-                        let arc = (end_angle - start_angle) / segments as f32;
-                        let rotor = [arc.cos(), -arc.sin()];
-                        let mut angle = [start_angle.cos(), -start_angle.sin()];
-                        let center = [pos[0] - radius * angle[0], pos[1] - radius * angle[1]];
-                        for _ in 0..segments {
-                            verts.push([center[0] + radius * angle[0], center[1] + radius * angle[1]]);
-                            angle = [rotor[0] * angle[0] - rotor[1] * angle[1], rotor[0] * angle[1] + rotor[1] * angle[0]];
+                    let (glyph_verts, glyph_indices) = &mut batches[page];
+                    glyph_quad(glyph_verts, glyph_indices, glyph, rect, &atlas.pages[page].atlas, col, width, height);
+                }
+            }
+
+            for (page, (glyph_verts, glyph_indices)) in batches.iter().enumerate() {
+                if !glyph_indices.is_empty() {
+                    self.renderer.draw_tex(glyph_verts, glyph_indices, atlas.pages[page].tex);
+                }
+            }
+        }
+
+        for quad in effect_quads {
+            let effect = self.effects.get(quad.effect).unwrap();
+            let col = [1.0; 4];
+            if let Some(shader) = effect.shader {
+                let mut verts: Vec<VertexUV> = Vec::new();
+                let mut indices: Vec<u16> = Vec::new();
+                tex_quad(&mut verts, &mut indices, quad.pos, quad.size, [0.0, 0.0], [1.0, 1.0], col, width, height);
+                let uniforms: Vec<(&str, Uniform)> = quad.uniforms.iter().map(|(name, value)| (&name[..], *value)).collect();
+                self.renderer.draw_custom(shader, &verts, &indices, &uniforms);
+            } else if let Some(fallback) = effect.fallback.as_ref() {
+                // the fallback is evaluated at the center of every pixel into a texture used for this draw only
+                let tex_width = quad.size[0].ceil().max(1.0) as usize;
+                let tex_height = quad.size[1].ceil().max(1.0) as usize;
+                let mut pixels = Vec::with_capacity(tex_width * tex_height * 4);
+                for y in 0..tex_height {
+                    for x in 0..tex_width {
+                        let uv = [(x as f32 + 0.5) / tex_width as f32, (y as f32 + 0.5) / tex_height as f32];
+                        let color = fallback(uv, &quad.uniforms);
+                        for channel in [color.r, color.g, color.b, color.a].iter() {
+                            pixels.push((channel.max(0.0).min(1.0) * 255.0).round() as u8);
                         }
                     }
                 }
+                let tex = match self.renderer.create_tex(TexFormat::SRGBA, TexOptions::default(), tex_width, tex_height, &pixels) {
+                    Ok(tex) => tex,
+                    Err(_) => continue,
+                };
+                let mut verts: Vec<VertexUV> = Vec::new();
+                let mut indices: Vec<u16> = Vec::new();
+                // textures are stored bottom up, so the top of the quad is at v = 1
+                tex_quad(&mut verts, &mut indices, quad.pos, quad.size, [0.0, 1.0], [1.0, 0.0], col, width, height);
+                self.renderer.draw_tex(&verts, &indices, tex);
+                self.renderer.delete_tex(tex);
             }
-            let path_start = path_verts.len();
-            for i in 0..verts.len() {
-                let prev = verts[(i+verts.len()-1)%verts.len()];
-                let curr = verts[i];
-                let next = verts[(i+1)%verts.len()];
-                let prev_normal = normalized([prev[1] - curr[1], curr[0] - prev[0]]);
-                let next_normal = normalized([curr[1] - next[1], next[0] - curr[0]]);
-                let normal = normalized([(prev_normal[0] + next_normal[0]) / 2.0, (prev_normal[1] + next_normal[1]) / 2.0]);
-                let (inner_x, inner_y) = pixel_to_ndc(curr[0] - 0.5 * normal[0], curr[1] - 0.5 * normal[1], width, height);
-                let (outer_x, outer_y) = pixel_to_ndc(curr[0] + 0.5 * normal[0], curr[1] + 0.5 * normal[1], width, height);
-                path_verts.push(Vertex { pos: [inner_x, inner_y, 0.0], col });
-                path_verts.push(Vertex { pos: [outer_x, outer_y, 0.0], col: col_alpha });
-            }
-            for i in 1..verts.len().saturating_sub(1) {
-                path_indices.extend_from_slice(&[path_start as u16, (path_start + 2*i) as u16, (path_start + 2*i + 2) as u16]);
-            }
-            for i in 0..verts.len() {
-                path_indices.extend_from_slice(&[
-                    (path_start + 2*i) as u16, (path_start + 2*i + 1) as u16, (path_start + 2*((i+1)%verts.len()) + 1) as u16,
-                    (path_start + 2*i) as u16, (path_start + 2*((i+1)%verts.len()) + 1) as u16, (path_start + 2*((i+1)%verts.len())) as u16,
-                ]);
+        }
+    }
+
+    fn draw_atlas_overlay(&mut self, width: f32, height: f32) {
+        const PAGE_SIZE: f32 = 256.0;
+        let background = Color::rgba(0.0, 0.0, 0.0, 0.8).vertex_color(BlendMode::Normal);
+        let boundary = Color::rgba(1.0, 0.2, 0.2, 0.8).vertex_color(BlendMode::Normal);
+        let mut x = 0.0;
+        for atlas in [&self.atlas, &self.lcd_atlas, &self.sdf_atlas, &self.color_atlas].iter() {
+            for page in atlas.pages.iter() {
+                let mut verts: Vec<Vertex> = Vec::new();
+                let mut indices: Vec<u16> = Vec::new();
+                rect_quad(&mut verts, &mut indices, [x, 0.0], [PAGE_SIZE, PAGE_SIZE], background, width, height);
+                self.renderer.draw(&verts, &indices);
+
+                let (x1, y1) = pixel_to_ndc(x, 0.0, width, height);
+                let (x2, y2) = pixel_to_ndc(x + PAGE_SIZE, PAGE_SIZE, width, height);
+                let col = [1.0, 1.0, 1.0, 1.0];
+                // textures are stored bottom up, so the top of the page is at v = 1
+                let tex_verts = [
+                    VertexUV { pos: [x1, y1, 0.0], col, uv: [0.0, 1.0] },
+                    VertexUV { pos: [x2, y1, 0.0], col, uv: [1.0, 1.0] },
+                    VertexUV { pos: [x2, y2, 0.0], col, uv: [1.0, 0.0] },
+                    VertexUV { pos: [x1, y2, 0.0], col, uv: [0.0, 0.0] },
+                ];
+                self.renderer.draw_tex(&tex_verts, &[0, 1, 2, 0, 2, 3], page.tex);
+
+                let mut verts: Vec<Vertex> = Vec::new();
+                let mut indices: Vec<u16> = Vec::new();
+                for row in page.atlas.rows.iter() {
+                    let y = PAGE_SIZE * (1.0 - row.y as f32 / ATLAS_SIZE as f32);
+                    rect_quad(&mut verts, &mut indices, [x, y - 1.0], [PAGE_SIZE, 1.0], boundary, width, height);
+                }
+                self.renderer.draw(&verts, &indices);
+
+                x += PAGE_SIZE + 4.0;
             }
         }
-        self.renderer.draw(&path_verts, &path_indices);
+    }
 
-        let mut glyph_verts: Vec<VertexUV> = Vec::new();
-        let mut glyph_indices: Vec<u16> = Vec::new();
-        self.atlas.update_counter();
+    // Rasterizes the glyphs of `text` at each of the given scales ahead of time, using the
+    // current glyph mode, so that they are already in the atlas when first drawn.
+    pub fn prewarm(&mut self, text: &str, font_id: FontId, scales: &[f32]) {
+        let mode = self.glyph_mode;
+        let cpu_sdf = !self.renderer.supports_distance_field();
+        let atlas = match mode {
+            GlyphMode::Bitmap => &mut self.atlas,
+            GlyphMode::Subpixel => &mut self.lcd_atlas,
+            GlyphMode::DistanceField => &mut self.sdf_atlas,
+        };
+        let font_data = self.fonts.get(font_id).unwrap();
+        let font = font_data.font();
+        for &scale in scales {
+            let raster_scale = match mode {
+                GlyphMode::DistanceField if !cpu_sdf => SDF_SCALE,
+                _ => quantize_scale(scale),
+            };
+            for c in text.chars() {
+                if let Some(glyph) = font.lookup_glyph_id(c as u32) {
+                    if glyph_offset(font, glyph, raster_scale, mode).is_some() {
+                        let id = GlyphId { font: font_id, scale: raster_scale, glyph, foreground: [0; 3] };
+                        atlas.get_or_insert(&mut self.renderer, id, || Some(rasterize(font, id, mode, cpu_sdf)));
+                    }
+                }
+            }
+        }
+    }
 
-        for (color, glyph_list) in glyphs {
-            let col = color.to_linear();
-            for glyph in glyph_list.iter() {
-                let rect = if let Some(rect) = self.atlas.get_cached(glyph.id) {
-                    rect
-                } else {
-                    let font = self.fonts.get(glyph.id.font).unwrap();
-                    let bbox = font.get_bbox(glyph.id.glyph, glyph.id.scale).unwrap();
-                    let rect = self.atlas.insert(glyph.id, bbox.width() as u32, bbox.height() as u32).unwrap();
-                    let rendered = font.render_glyph(glyph.id.glyph, glyph.id.scale).unwrap();
-                    self.renderer.update_tex(self.atlas_tex, rect.x as usize, rect.y as usize, rendered.width as usize, rendered.height as usize, &rendered.data);
-                    rect
-                };
+    // Writes the bitmaps of every glyph currently in the atlases to a file, keyed by font
+    // contents rather than FontId so that the file can be loaded in another run. Distance
+    // fields evaluated on the CPU are left out, as they are not distance fields.
+    pub fn save_glyph_cache<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut glyphs = Vec::new();
+        for &mode in [GlyphMode::Bitmap, GlyphMode::Subpixel, GlyphMode::DistanceField].iter() {
+            let atlas = match mode {
+                GlyphMode::Bitmap => &self.atlas,
+                GlyphMode::Subpixel => &self.lcd_atlas,
+                GlyphMode::DistanceField => &self.sdf_atlas,
+            };
+            let bitmaps = match atlas.bitmaps.as_ref() {
+                Some(bitmaps) => bitmaps,
+                None => continue,
+            };
+            for (id, (width, height, data)) in bitmaps.iter() {
+                if let Some(font) = self.fonts.get(id.font) {
+                    glyphs.push(CachedGlyph { mode: mode as u8, font: font.hash(), scale: id.scale, glyph: id.glyph, width: *width, height: *height, data: data.clone() });
+                }
+            }
+        }
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        write_glyph_cache(&mut file, &glyphs)
+    }
 
-                let i = glyph_verts.len() as u16;
-                let (u1, v1) = (rect.x as f32 / self.atlas.width as f32, (rect.y + rect.h) as f32 / self.atlas.height as f32);
-                let (u2, v2) = ((rect.x + rect.w) as f32 / self.atlas.width as f32, rect.y as f32 / self.atlas.height as f32);
-                let (x1, y1) = pixel_to_ndc(glyph.pos[0], glyph.pos[1], width, height);
-                let (x2, y2) = pixel_to_ndc(glyph.pos[0] + rect.w as f32, glyph.pos[1] + rect.h as f32, width, height);
-                glyph_verts.extend_from_slice(&[VertexUV {
-                    pos: [x1, y1, 0.0],
-                    col,
-                    uv: [u1, v1],
-                }, VertexUV {
-                    pos: [x2, y1, 0.0],
-                    col,
-                    uv: [u2, v1],
-                }, VertexUV {
-                    pos: [x2, y2, 0.0],
-                    col,
-                    uv: [u2, v2],
-                }, VertexUV {
-                    pos: [x1, y2, 0.0],
-                    col,
-                    uv: [u1, v2],
-                }]);
-                glyph_indices.extend_from_slice(&[i, i+1, i+2, i, i+2, i+3]);
-            }
-        }
-        self.renderer.draw_tex(&glyph_verts, &glyph_indices, self.atlas_tex);
+    // Loads glyphs saved by save_glyph_cache into the atlases without rasterizing them.
+    // Glyphs of fonts that are not currently loaded are skipped.
+    pub fn load_glyph_cache<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+        for glyph in read_glyph_cache(&mut file)? {
+            let font = match self.fonts.find_hash(glyph.font) {
+                Some(font) => font,
+                None => continue,
+            };
+            let atlas = match GlyphMode::from_u8(glyph.mode) {
+                Some(GlyphMode::Bitmap) => &mut self.atlas,
+                Some(GlyphMode::Subpixel) => &mut self.lcd_atlas,
+                Some(GlyphMode::DistanceField) if self.renderer.supports_distance_field() => &mut self.sdf_atlas,
+                _ => continue,
+            };
+            // glyphs read before a malformed one stay loaded
+            let size = glyph.width as usize * glyph.height as usize * atlas.format.bytes_per_pixel();
+            if glyph.width > ATLAS_SIZE || glyph.height > ATLAS_SIZE || glyph.data.len() != size {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "glyph size does not match its data"));
+            }
+            let id = GlyphId { font, scale: glyph.scale, glyph: glyph.glyph, foreground: [0; 3] };
+            if atlas.get_cached(id).is_none() {
+                atlas.insert_data(&mut self.renderer, id, glyph.width, glyph.height, &glyph.data);
+            }
+        }
+        Ok(())
+    }
 
-        self.layers = Vec::new();
-        self.stack = Vec::new();
-        self.items = Vec::new();
-        self.glyphs = Vec::new();
-        self.paths = Vec::new();
+    // Registers a fragment shader for effect quads, compiled as described at
+    // Renderer::create_shader. v_uv runs from (0, 0) at the top left of the quad to (1, 1)
+    // at the bottom right. The fallback computes the color at a given uv on the CPU, and
+    // is used in place of the shader when it fails to compile.
+    pub fn add_effect(&mut self, source: &str, fallback: Option<EffectFallback>) -> Result<EffectId, RendererError> {
+        let shader = match self.renderer.create_shader(source) {
+            Ok(shader) => Some(shader),
+            Err(_) if fallback.is_some() => None,
+            Err(error) => return Err(error),
+        };
+        Ok(self.effects.insert(Effect { shader, fallback }))
+    }
+
+    pub fn remove_effect(&mut self, effect: EffectId) {
+        if let Some(Effect { shader: Some(shader), .. }) = self.effects.remove(effect) {
+            self.renderer.delete_shader(shader);
+        }
     }
 
-    pub fn text_size(&self, text: &str, font_id: FontId, scale: u32) -> (f32, f32) {
-        let font = self.fonts.get(font_id).unwrap();
+    pub fn text_size(&self, text: &str, font_id: FontId, scale: f32) -> (f32, f32) {
+        let font = self.fonts.get(font_id).unwrap().font();
         let mut width = 0.0;
         for c in text.chars() {
             let glyph = font.lookup_glyph_id(c as u32).unwrap();
-            let h_metrics = font.get_h_metrics(glyph, scale).unwrap();
-            width += h_metrics.advance_width;
+            let h_metrics = font.get_h_metrics(glyph, 1).unwrap();
+            width += h_metrics.advance_width * scale;
         }
-        let v_metrics = font.get_v_metrics(scale).unwrap();
-        (width, v_metrics.ascent - v_metrics.descent)
+        let v_metrics = font.get_v_metrics(1).unwrap();
+        (width, (v_metrics.ascent - v_metrics.descent) * scale)
     }
 
-    pub fn text(&mut self, pos: [f32; 2], text: &str, font_id: FontId, scale: u32, color: Color) {
-        let font = self.fonts.get(font_id).unwrap();
+    pub fn text(&mut self, pos: [f32; 2], text: &str, font_id: FontId, scale: f32, color: Color) {
+        let font_data = self.fonts.get(font_id).unwrap();
+        let font = font_data.font();
+        let color_glyphs = font_data.color_glyphs();
+        // glyphs are drawn scaled from the size they were rasterized at to the exact size, and
+        // distance field glyphs are rasterized once at a fixed size
+        let (mono_scale, mono_quad_scale) = match self.glyph_mode {
+            GlyphMode::Bitmap | GlyphMode::Subpixel => (quantize_scale(scale), scale / quantize_scale(scale) as f32),
+            GlyphMode::DistanceField if self.renderer.supports_distance_field() => (SDF_SCALE, scale / SDF_SCALE as f32),
+            // evaluated on the CPU at the size drawn, like bitmaps
+            GlyphMode::DistanceField => (quantize_scale(scale), scale / quantize_scale(scale) as f32),
+        };
         let mut pos = pos;
-        let start = self.glyphs.len();
+        let mut start = self.glyphs.len();
+        let mut run_is_color = false;
+        let mut run_x = pos[0];
         self.glyphs.reserve(text.len());
+        let v_metrics = font.get_v_metrics(1).unwrap();
         for c in text.chars() {
             let glyph = font.lookup_glyph_id(c as u32).unwrap();
-            let h_metrics = font.get_h_metrics(glyph, scale).unwrap();
-            let v_metrics = font.get_v_metrics(scale).unwrap();
-            if let Some(bbox) = font.get_bbox(glyph, scale) {
+            let h_metrics = font.get_h_metrics(glyph, 1).unwrap();
+            // runs of monochrome and color glyphs become separate display items, in text order
+            let is_color = color_glyphs.contains(glyph);
+            if is_color != run_is_color {
+                if self.glyphs.len() > start {
+                    // the font is still borrowed, so push_item can't be used here
+                    self.items.push(glyph_run(color, self.blend_mode, run_is_color, self.glyph_mode, start, self.glyphs.len()));
+                    self.item_bounds.push(run_bounds(run_x, pos[0], pos[1], (v_metrics.ascent - v_metrics.descent) * scale, scale));
+                }
+                start = self.glyphs.len();
+                run_is_color = is_color;
+                run_x = pos[0];
+            }
+            let (raster_scale, quad_scale, offset) = if is_color {
+                let raster_scale = quantize_scale(scale);
+                (raster_scale, scale / raster_scale as f32, color_glyphs.offset(font, font_data.data(), glyph, raster_scale))
+            } else {
+                (mono_scale, mono_quad_scale, glyph_offset(font, glyph, mono_scale, self.glyph_mode))
+            };
+            // the text color is rendered into the glyph, so each color is cached separately
+            let foreground = if is_color && color_glyphs.uses_foreground(glyph) { color.to_srgb8() } else { [0; 3] };
+            if let Some(offset) = offset {
                 self.glyphs.push(Glyph {
-                    id: GlyphId { font: font_id, scale, glyph },
-                    pos: [pos[0] + bbox.l as f32, pos[1] + bbox.t as f32 + v_metrics.ascent as f32],
+                    id: GlyphId { font: font_id, scale: raster_scale, glyph, foreground },
+                    pos: [
+                        pos[0] + offset[0] as f32 * quad_scale,
+                        pos[1] + offset[1] as f32 * quad_scale + v_metrics.ascent * scale,
+                    ],
+                    scale: quad_scale,
                 });
             }
-            pos[0] += h_metrics.advance_width;
+            pos[0] += h_metrics.advance_width * scale;
         }
-        self.items.push(DisplayItem::Glyphs(color, start, self.glyphs.len()));
+        let bounds = run_bounds(run_x, pos[0], pos[1], (v_metrics.ascent - v_metrics.descent) * scale, scale);
+        self.push_item(glyph_run(color, self.blend_mode, run_is_color, self.glyph_mode, start, self.glyphs.len()), bounds);
     }
 
     pub fn rect_fill(&mut self, pos: [f32; 2], size: [f32; 2], color: Color) {
+        if self.path_mode == PathMode::Coverage {
+            self.shape_fill(pos, size, 0.0, color);
+            return;
+        }
         let start = self.paths.len();
         self.paths.extend_from_slice(&[
             PathSegment([pos[0], pos[1]], SegmentType::Line),
@@ -209,10 +858,15 @@ impl Graphics {
             PathSegment([pos[0] + size[0], pos[1] + size[1]], SegmentType::Line),
             PathSegment([pos[0] + size[0], pos[1]], SegmentType::Line),
         ]);
-        self.items.push(DisplayItem::FillPath(color, start, self.paths.len()));
+        let bounds = path_bounds(&self.paths[start..]);
+        self.push_item(DisplayItem::FillPath(color, self.blend_mode, start, self.paths.len()), bounds);
     }
 
     pub fn round_rect_fill(&mut self, pos: [f32; 2], size: [f32; 2], radius: f32, color: Color) {
+        if self.path_mode == PathMode::Coverage {
+            self.shape_fill(pos, size, radius, color);
+            return;
+        }
         let start = self.paths.len();
         self.paths.extend_from_slice(&[
             PathSegment([pos[0] + radius, pos[1]], SegmentType::Arc(radius, PI/2.0, PI)),
@@ -224,16 +878,232 @@ impl Graphics {
             PathSegment([pos[0] + size[0], pos[1] + radius], SegmentType::Arc(radius, 0.0, PI/2.0)),
             PathSegment([pos[0] + size[0] - radius, pos[1]], SegmentType::Line),
         ]);
-        self.items.push(DisplayItem::FillPath(color, start, self.paths.len()));
+        let bounds = path_bounds(&self.paths[start..]);
+        self.push_item(DisplayItem::FillPath(color, self.blend_mode, start, self.paths.len()), bounds);
+    }
+
+    pub fn effect(&mut self, pos: [f32; 2], size: [f32; 2], effect: EffectId, uniforms: &[(&str, Uniform)]) {
+        self.effect_quads.push(EffectQuad {
+            effect,
+            pos,
+            size,
+            uniforms: uniforms.iter().map(|(name, value)| (name.to_string(), *value)).collect(),
+        });
+        let bounds = Bounds { x: pos[0], y: pos[1], width: size[0], height: size[1] };
+        self.push_item(DisplayItem::Custom(self.blend_mode, self.effect_quads.len() - 1), bounds);
     }
 
     pub fn circle_fill(&mut self, pos: [f32; 2], radius: f32, color: Color) {
+        if self.path_mode == PathMode::Coverage {
+            self.shape_fill([pos[0] - radius, pos[1] - radius], [2.0 * radius, 2.0 * radius], radius, color);
+            return;
+        }
         let start = self.paths.len();
         self.paths.extend_from_slice(&[
             PathSegment([pos[0] + radius, pos[1]], SegmentType::Arc(radius, 0.0, 2.0*PI)),
         ]);
-        self.items.push(DisplayItem::FillPath(color, start, self.paths.len()));
+        let bounds = path_bounds(&self.paths[start..]);
+        self.push_item(DisplayItem::FillPath(color, self.blend_mode, start, self.paths.len()), bounds);
+    }
+
+    // Fills a closed polygon. With PathMode::Fringe it is triangulated as a fan, which is
+    // only correct for convex polygons, while PathMode::Coverage fills any polygon with the
+    // nonzero rule, antialiased by a fringe around its outside edges.
+    pub fn polygon_fill(&mut self, points: &[[f32; 2]], color: Color) {
+        if points.len() < 3 { return; }
+        let start = self.paths.len();
+        self.paths.extend(points.iter().map(|&point| PathSegment(point, SegmentType::Line)));
+        let bounds = path_bounds(&self.paths[start..]);
+        let (item, bounds) = match self.path_mode {
+            PathMode::Fringe => (DisplayItem::FillPath(color, self.blend_mode, start, self.paths.len()), bounds),
+            // the fringe reaches half a pixel past the polygon
+            PathMode::Coverage => {
+                let bounds = Bounds { x: bounds.x - 1.0, y: bounds.y - 1.0, width: bounds.width + 2.0, height: bounds.height + 2.0 };
+                (DisplayItem::StencilPath(color, self.blend_mode, start, self.paths.len()), bounds)
+            }
+        };
+        self.push_item(item, bounds);
+    }
+
+    fn shape_fill(&mut self, pos: [f32; 2], size: [f32; 2], radius: f32, color: Color) {
+        self.shapes.push(Shape { pos, size, radius });
+        let bounds = Bounds { x: pos[0] - 1.0, y: pos[1] - 1.0, width: size[0] + 2.0, height: size[1] + 2.0 };
+        self.push_item(DisplayItem::Shape(color, self.blend_mode, self.shapes.len() - 1), bounds);
+    }
+}
+
+// font-rs metrics are linear in the pixel size, so metrics are queried at size 1 and
+// multiplied by the exact scale. font-rs only rasterizes at whole pixel sizes, so bitmaps
+// are rasterized at the nearest one, which also keeps the atlas to one entry per integer
+// size, and their quads are scaled by the remaining fraction.
+#[inline]
+fn quantize_scale(scale: f32) -> u32 {
+    scale.round().max(1.0) as u32
+}
+
+// Offset of a rasterized glyph's top left corner from the pen position, in raster pixels.
+fn glyph_offset(font: &font_rs::font::Font, glyph: u16, scale: u32, mode: GlyphMode) -> Option<[i32; 2]> {
+    match mode {
+        GlyphMode::Bitmap => {
+            font.get_bbox(glyph, scale).map(|bbox| [bbox.l, bbox.t])
+        }
+        GlyphMode::Subpixel => {
+            font.get_bbox(glyph, 3 * scale).map(|bbox| {
+                let (x, y) = subpixel_origin(bbox.l, bbox.t);
+                [x, y]
+            })
+        }
+        GlyphMode::DistanceField => {
+            font.get_bbox(glyph, scale).map(|bbox| [bbox.l - SDF_SPREAD as i32, bbox.t - SDF_SPREAD as i32])
+        }
+    }
+}
+
+// With cpu_sdf, distance fields are evaluated into coverage as the SDF shader would.
+fn rasterize(font: &font_rs::font::Font, id: GlyphId, mode: GlyphMode, cpu_sdf: bool) -> (u32, u32, Vec<u8>) {
+    match mode {
+        GlyphMode::Bitmap => {
+            let rendered = font.render_glyph(id.glyph, id.scale).unwrap();
+            (rendered.width as u32, rendered.height as u32, rendered.data)
+        }
+        GlyphMode::Subpixel => {
+            let bbox = font.get_bbox(id.glyph, 3 * id.scale).unwrap();
+            let rendered = font.render_glyph(id.glyph, 3 * id.scale).unwrap();
+            let (width, height, data) = subpixel_coverage(&rendered.data, rendered.width, rendered.height, bbox.l, bbox.t);
+            (width as u32, height as u32, data)
+        }
+        GlyphMode::DistanceField => {
+            let rendered = font.render_glyph(id.glyph, id.scale).unwrap();
+            let field = distance_field(&rendered.data, rendered.width, rendered.height, SDF_SPREAD as usize);
+            let data = if cpu_sdf { field_coverage(&field, SDF_SPREAD as usize) } else { field };
+            (rendered.width as u32 + 2 * SDF_SPREAD, rendered.height as u32 + 2 * SDF_SPREAD, data)
+        }
+    }
+}
+
+fn rasterize_color(font: &FontData, id: GlyphId) -> Option<(u32, u32, Vec<u8>)> {
+    let [r, g, b] = id.foreground;
+    let bitmap = font.color_glyphs().rasterize(font.font(), font.data(), id.glyph, id.scale, [r, g, b, 255])?;
+    Some((bitmap.width, bitmap.height, bitmap.data))
+}
+
+// Glyphs can extend past their advance and the line's ascent and descent, so the bounds of
+// a run are padded generously.
+fn run_bounds(x1: f32, x2: f32, y: f32, line_height: f32, scale: f32) -> Bounds {
+    let pad = 0.5 * scale + SDF_SPREAD as f32;
+    Bounds { x: x1 - pad, y: y - pad, width: x2 - x1 + 2.0 * pad, height: line_height + 2.0 * pad }
+}
+
+fn path_bounds(path: &[PathSegment]) -> Bounds {
+    let (mut min, mut max) = ([f32::INFINITY; 2], [f32::NEG_INFINITY; 2]);
+    for PathSegment(pos, segment) in path.iter() {
+        let (center, radius) = match *segment {
+            SegmentType::Line => (*pos, 0.0),
+            SegmentType::Arc(radius, start_angle, _) => ([pos[0] - radius * start_angle.cos(), pos[1] + radius * start_angle.sin()], radius),
+        };
+        min = [min[0].min(center[0] - radius), min[1].min(center[1] - radius)];
+        max = [max[0].max(center[0] + radius), max[1].max(center[1] + radius)];
+    }
+    // the antialiased edge extends half a pixel outwards
+    Bounds { x: min[0] - 1.0, y: min[1] - 1.0, width: max[0] - min[0] + 2.0, height: max[1] - min[1] + 2.0 }
+}
+
+fn hash_floats<H: Hasher>(hasher: &mut H, floats: &[f32]) {
+    for x in floats {
+        hasher.write_u32(x.to_bits());
+    }
+}
+
+// Regions covered by items that are in only one of two frames, where items are identified by
+// their hash. Overlapping regions are merged, and too many regions are replaced by their
+// union, as each region is a separate pass over the display list.
+fn damage(last: &[(u64, Bounds)], current: &[(u64, Bounds)], screen: Bounds) -> Vec<Bounds> {
+    let mut rects = Vec::new();
+    for &(frame, other) in [(last, current), (current, last)].iter() {
+        let mut counts: HashMap<u64, usize> = HashMap::new();
+        for (hash, _) in other.iter() {
+            *counts.entry(*hash).or_insert(0) += 1;
+        }
+        for (hash, bounds) in frame.iter() {
+            match counts.get_mut(hash) {
+                Some(count) if *count > 0 => *count -= 1,
+                _ => rects.extend(bounds.intersection(&screen)),
+            }
+        }
     }
+
+    let mut merged: Vec<Bounds> = Vec::new();
+    for mut rect in rects {
+        while let Some(i) = merged.iter().position(|other| other.intersects(&rect)) {
+            rect = rect.union(&merged.swap_remove(i));
+        }
+        merged.push(rect);
+    }
+    if merged.len() > MAX_DAMAGE_RECTS {
+        let union = merged.iter().skip(1).fold(merged[0], |union, rect| union.union(rect));
+        merged = vec![union];
+    }
+    merged
+}
+
+fn glyph_run(color: Color, blend: BlendMode, is_color: bool, mode: GlyphMode, start: usize, end: usize) -> DisplayItem {
+    if is_color {
+        DisplayItem::ColorGlyphs(color, blend, start, end)
+    } else {
+        DisplayItem::Glyphs(color, blend, mode, start, end)
+    }
+}
+
+fn rect_quad(verts: &mut Vec<Vertex>, indices: &mut Vec<u16>, pos: [f32; 2], size: [f32; 2], col: [f32; 4], width: f32, height: f32) {
+    let i = verts.len() as u16;
+    let (x1, y1) = pixel_to_ndc(pos[0], pos[1], width, height);
+    let (x2, y2) = pixel_to_ndc(pos[0] + size[0], pos[1] + size[1], width, height);
+    verts.extend_from_slice(&[
+        Vertex { pos: [x1, y1, 0.0], col },
+        Vertex { pos: [x2, y1, 0.0], col },
+        Vertex { pos: [x2, y2, 0.0], col },
+        Vertex { pos: [x1, y2, 0.0], col },
+    ]);
+    indices.extend_from_slice(&[i, i+1, i+2, i, i+2, i+3]);
+}
+
+fn tex_quad(verts: &mut Vec<VertexUV>, indices: &mut Vec<u16>, pos: [f32; 2], size: [f32; 2], uv1: [f32; 2], uv2: [f32; 2], col: [f32; 4], width: f32, height: f32) {
+    let i = verts.len() as u16;
+    let (x1, y1) = pixel_to_ndc(pos[0], pos[1], width, height);
+    let (x2, y2) = pixel_to_ndc(pos[0] + size[0], pos[1] + size[1], width, height);
+    verts.extend_from_slice(&[
+        VertexUV { pos: [x1, y1, 0.0], col, uv: [uv1[0], uv1[1]] },
+        VertexUV { pos: [x2, y1, 0.0], col, uv: [uv2[0], uv1[1]] },
+        VertexUV { pos: [x2, y2, 0.0], col, uv: [uv2[0], uv2[1]] },
+        VertexUV { pos: [x1, y2, 0.0], col, uv: [uv1[0], uv2[1]] },
+    ]);
+    indices.extend_from_slice(&[i, i+1, i+2, i, i+2, i+3]);
+}
+
+fn glyph_quad(verts: &mut Vec<VertexUV>, indices: &mut Vec<u16>, glyph: &Glyph, rect: Rect, atlas: &Atlas, col: [f32; 4], width: f32, height: f32) {
+    let i = verts.len() as u16;
+    let (u1, v1) = (rect.x as f32 / atlas.width as f32, (rect.y + rect.h) as f32 / atlas.height as f32);
+    let (u2, v2) = ((rect.x + rect.w) as f32 / atlas.width as f32, rect.y as f32 / atlas.height as f32);
+    let (x1, y1) = pixel_to_ndc(glyph.pos[0], glyph.pos[1], width, height);
+    let (x2, y2) = pixel_to_ndc(glyph.pos[0] + rect.w as f32 * glyph.scale, glyph.pos[1] + rect.h as f32 * glyph.scale, width, height);
+    verts.extend_from_slice(&[VertexUV {
+        pos: [x1, y1, 0.0],
+        col,
+        uv: [u1, v1],
+    }, VertexUV {
+        pos: [x2, y1, 0.0],
+        col,
+        uv: [u2, v1],
+    }, VertexUV {
+        pos: [x2, y2, 0.0],
+        col,
+        uv: [u2, v2],
+    }, VertexUV {
+        pos: [x1, y2, 0.0],
+        col,
+        uv: [u1, v2],
+    }]);
+    indices.extend_from_slice(&[i, i+1, i+2, i, i+2, i+3]);
 }
 
 #[inline]
@@ -260,11 +1130,66 @@ fn normalized(p: [f32; 2]) -> [f32; 2] {
 
 #[derive(Copy, Clone)]
 pub enum DisplayItem {
-    Glyphs(Color, usize, usize),
-    FillPath(Color, usize, usize),
+    Glyphs(Color, BlendMode, GlyphMode, usize, usize),
+    ColorGlyphs(Color, BlendMode, usize, usize),
+    FillPath(Color, BlendMode, usize, usize),
+    // an index into the shapes
+    Shape(Color, BlendMode, usize),
+    StencilPath(Color, BlendMode, usize, usize),
+    // an index into the effect quads
+    Custom(BlendMode, usize),
 }
 
-#[derive(Copy, Clone)]
+impl DisplayItem {
+    fn blend_mode(&self) -> BlendMode {
+        match *self {
+            DisplayItem::Glyphs(_, blend, ..) => blend,
+            DisplayItem::ColorGlyphs(_, blend, ..) => blend,
+            DisplayItem::FillPath(_, blend, ..) => blend,
+            DisplayItem::Shape(_, blend, _) => blend,
+            DisplayItem::StencilPath(_, blend, ..) => blend,
+            DisplayItem::Custom(blend, _) => blend,
+        }
+    }
+
+    // Items can only be drawn in one batch if this is the same for all of them. A batch
+    // draws each kind of item, and glyphs of each mode, separately, so mixing them would
+    // change the order they were recorded in.
+    fn batch(&self) -> (BlendMode, std::mem::Discriminant<DisplayItem>, Option<GlyphMode>) {
+        let mode = match *self { DisplayItem::Glyphs(_, _, mode, ..) => Some(mode), _ => None };
+        (self.blend_mode(), std::mem::discriminant(self), mode)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Bounds {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Bounds {
+    fn intersects(&self, other: &Bounds) -> bool {
+        self.x < other.x + other.width && other.x < self.x + self.width &&
+        self.y < other.y + other.height && other.y < self.y + self.height
+    }
+
+    fn intersection(&self, other: &Bounds) -> Option<Bounds> {
+        if !self.intersects(other) { return None; }
+        let (x, y) = (self.x.max(other.x), self.y.max(other.y));
+        let (x2, y2) = ((self.x + self.width).min(other.x + other.width), (self.y + self.height).min(other.y + other.height));
+        Some(Bounds { x, y, width: x2 - x, height: y2 - y })
+    }
+
+    fn union(&self, other: &Bounds) -> Bounds {
+        let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+        let (x2, y2) = ((self.x + self.width).max(other.x + other.width), (self.y + self.height).max(other.y + other.height));
+        Bounds { x, y, width: x2 - x, height: y2 - y }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
 pub struct Color {
     r: f32, g: f32, b: f32, a: f32
 }
@@ -277,35 +1202,405 @@ impl Color {
     fn to_linear(&self) -> [f32; 4] {
         [srgb_to_linear(self.r), srgb_to_linear(self.g), srgb_to_linear(self.b), self.a]
     }
+
+    fn to_srgb8(&self) -> [u8; 3] {
+        let channel = |c: f32| (c.max(0.0).min(1.0) * 255.0).round() as u8;
+        [channel(self.r), channel(self.g), channel(self.b)]
+    }
+
+    // Linear and premultiplied, as the shaders expect. With BlendMode::Premultiplied the
+    // color is taken to be premultiplied already.
+    fn vertex_color(&self, blend: BlendMode) -> [f32; 4] {
+        let [r, g, b, a] = self.to_linear();
+        match blend {
+            BlendMode::Premultiplied => [r, g, b, a],
+            _ => [r * a, g * a, b * a, a],
+        }
+    }
 }
 
-fn srgb_to_linear(x: f32) -> f32 {
-    if x < 0.04045 { x / 12.92 } else { ((x + 0.055)/1.055).powf(2.4)  }
+pub type EffectId = usize;
+
+// Color at a uv within the quad, given the quad's uniforms.
+pub type EffectFallback = Box<dyn Fn([f32; 2], &[(String, Uniform)]) -> Color>;
+
+struct Effect {
+    shader: Option<ShaderId>,
+    fallback: Option<EffectFallback>,
+}
+
+struct EffectQuad {
+    effect: EffectId,
+    pos: [f32; 2],
+    size: [f32; 2],
+    uniforms: Vec<(String, Uniform)>,
 }
 
 #[derive(Copy, Clone)]
 pub struct Glyph {
     id: GlyphId,
     pos: [f32; 2],
+    scale: f32,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AtlasStats {
+    pub pages: usize,
+    pub rows: usize,
+    // fraction of the total page area covered by cached glyphs
+    pub occupancy: f32,
+    // glyphs evicted during the last frame
+    pub evictions: usize,
+    // glyphs rasterized and uploaded during the last frame
+    pub uploads: usize,
+}
+
+// Where the time of a frame went, measured on the CPU. submit is the time spent in the
+// renderer's clears, draws and blits, and atlas_upload that of rasterizing and uploading
+// glyphs while drawing.
+// The GPU time is that of the previous frame drawn, and is only measured with the
+// gpu-timer feature on OpenGL 3.3.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct FrameStats {
+    // whether the frame differs from the last one and needs to be presented
+    pub changed: bool,
+    // time since the previous frame started, filled in by UI::run
+    pub interval: Duration,
+    pub layout: Duration,
+    pub render: Duration,
+    pub tessellation: Duration,
+    pub atlas_upload: Duration,
+    pub submit: Duration,
+    pub gpu: Option<Duration>,
+    pub vertices: usize,
+    pub draw_calls: usize,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum GlyphMode {
+    Bitmap,
+    Subpixel,
+    DistanceField,
+}
+
+impl GlyphMode {
+    fn from_u8(mode: u8) -> Option<GlyphMode> {
+        match mode {
+            0 => Some(GlyphMode::Bitmap),
+            1 => Some(GlyphMode::Subpixel),
+            2 => Some(GlyphMode::DistanceField),
+            _ => None,
+        }
+    }
+}
+
+// How fills are antialiased. Fringe surrounds tessellated paths with a pixel wide band of
+// vertices fading to transparent. Coverage computes the coverage of rectangles, rounded
+// rectangles and circles per fragment, and fills polygons through the stencil buffer with a
+// fringe drawn only outside of them.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PathMode {
+    Fringe,
+    Coverage,
 }
 
 #[derive(Copy, Clone)]
+struct Shape {
+    pos: [f32; 2],
+    size: [f32; 2],
+    radius: f32,
+}
+
+#[derive(Copy, Clone, PartialEq)]
 struct PathSegment([f32; 2], SegmentType);
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 enum SegmentType {
     Line,
     Arc(f32, f32, f32),
 }
 
 
-pub type FontId = usize;
-
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct GlyphId {
     font: FontId,
     scale: u32,
     glyph: u16,
+    // the sRGB text color of color glyphs with layers in that color, and black otherwise
+    foreground: [u8; 3],
+}
+
+// Tessellated paths, keyed by their geometry relative to their first point so that a path
+// drawn at another position reuses the same vertices. Entries unused for PATH_CACHE_FRAMES
+// frames are dropped.
+struct PathCache {
+    entries: HashMap<u64, CachedPath>,
+    counter: usize,
+}
+
+// Vertices are relative to the path's first point, and flagged when on the outer edge of
+// the antialiased fringe.
+struct CachedPath {
+    path: Vec<PathSegment>,
+    verts: Vec<([f32; 2], bool)>,
+    indices: Vec<u16>,
+    last_used: usize,
+}
+
+impl PathCache {
+    fn new() -> PathCache {
+        PathCache { entries: HashMap::new(), counter: 0 }
+    }
+
+    fn update_counter(&mut self) {
+        self.counter += 1;
+        let counter = self.counter;
+        self.entries.retain(|_, entry| counter - entry.last_used <= PATH_CACHE_FRAMES);
+    }
+
+    // Returns the translation of the path and its cached tessellation.
+    fn get(&mut self, path: &[PathSegment]) -> ([f32; 2], &CachedPath) {
+        let origin = path[0].0;
+        // relative positions are snapped to 1/256 of a pixel, so that rounding differences at
+        // different positions don't cause misses
+        let relative: Vec<PathSegment> = path.iter().map(|&PathSegment(pos, segment)| {
+            PathSegment([((pos[0] - origin[0]) * 256.0).round() / 256.0, ((pos[1] - origin[1]) * 256.0).round() / 256.0], segment)
+        }).collect();
+        let mut hasher = DefaultHasher::new();
+        for PathSegment(pos, segment) in relative.iter() {
+            hash_floats(&mut hasher, pos);
+            match *segment {
+                SegmentType::Line => 0u8.hash(&mut hasher),
+                SegmentType::Arc(radius, start_angle, end_angle) => {
+                    1u8.hash(&mut hasher);
+                    hash_floats(&mut hasher, &[radius, start_angle, end_angle]);
+                }
+            }
+        }
+        let key = hasher.finish();
+
+        let counter = self.counter;
+        let entry = self.entries.entry(key).or_insert_with(|| CachedPath { path: Vec::new(), verts: Vec::new(), indices: Vec::new(), last_used: counter });
+        // a hash collision replaces the entry
+        if entry.path != relative {
+            let (verts, indices) = tessellate(&relative);
+            *entry = CachedPath { path: relative, verts, indices, last_used: counter };
+        }
+        entry.last_used = counter;
+        (origin, entry)
+    }
+}
+
+// Replaces arcs with line segments within TOLERANCE of them.
+// A band along each edge of a polygon, fading from its color half a pixel inside to
+// transparent half a pixel outside, for Renderer::fill_stencil to draw where the polygon
+// doesn't cover. Outside is taken from the winding of the whole polygon.
+fn edge_fringe(points: &[[f32; 2]], col: [f32; 4], width: f32, height: f32) -> (Vec<Vertex>, Vec<u16>) {
+    let area: f32 = (0..points.len()).map(|i| {
+        let (a, b) = (points[i], points[(i + 1) % points.len()]);
+        a[0] * b[1] - b[0] * a[1]
+    }).sum();
+    let side = if area < 0.0 { -1.0 } else { 1.0 };
+    let mut verts = Vec::new();
+    let mut indices = Vec::new();
+    for i in 0..points.len() {
+        let (a, b) = (points[i], points[(i + 1) % points.len()]);
+        let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+        let length = (dx * dx + dy * dy).sqrt();
+        if length == 0.0 { continue; }
+        let normal = [0.5 * side * dy / length, -0.5 * side * dx / length];
+        let i = verts.len() as u16;
+        for &(point, offset, col) in [(a, -1.0, col), (b, -1.0, col), (b, 1.0, [0.0; 4]), (a, 1.0, [0.0; 4])].iter() {
+            let (x, y) = pixel_to_ndc(point[0] + offset * normal[0], point[1] + offset * normal[1], width, height);
+            verts.push(Vertex { pos: [x, y, 0.0], col });
+        }
+        indices.extend_from_slice(&[i, i+1, i+2, i, i+2, i+3]);
+    }
+    (verts, indices)
+}
+
+fn flatten(path: &[PathSegment]) -> Vec<[f32; 2]> {
+    let mut verts = Vec::new();
+    for (i, PathSegment(pos, segment)) in path.iter().enumerate() {
+        match segment {
+            SegmentType::Line => {
+                verts.push(*pos);
+            }
+            SegmentType::Arc(radius, start_angle, end_angle) => {
+                let PathSegment(next, _) = path[(i+1) % path.len()];
+                let segments: u16 = (((end_angle - start_angle).abs() / (1.0 - TOLERANCE / radius).acos()).ceil() as u16).max(4);
+                let arc = (end_angle - start_angle) / segments as f32;
+                let rotor = [arc.cos(), -arc.sin()];
+                let mut angle = [start_angle.cos(), -start_angle.sin()];
+                let center = [pos[0] - radius * angle[0], pos[1] - radius * angle[1]];
+                for _ in 0..segments {
+                    verts.push([center[0] + radius * angle[0], center[1] + radius * angle[1]]);
+                    angle = [rotor[0] * angle[0] - rotor[1] * angle[1], rotor[0] * angle[1] + rotor[1] * angle[0]];
+                }
+            }
+        }
+    }
+    verts
+}
+
+// Triangulates the flattened path as a fan, with a one pixel wide fringe that fades out
+// across the edge for antialiasing.
+fn tessellate(path: &[PathSegment]) -> (Vec<([f32; 2], bool)>, Vec<u16>) {
+    let verts = flatten(path);
+    let mut fringe_verts = Vec::with_capacity(2 * verts.len());
+    let mut indices = Vec::new();
+    for i in 0..verts.len() {
+        let prev = verts[(i+verts.len()-1)%verts.len()];
+        let curr = verts[i];
+        let next = verts[(i+1)%verts.len()];
+        let prev_normal = normalized([prev[1] - curr[1], curr[0] - prev[0]]);
+        let next_normal = normalized([curr[1] - next[1], next[0] - curr[0]]);
+        let normal = normalized([(prev_normal[0] + next_normal[0]) / 2.0, (prev_normal[1] + next_normal[1]) / 2.0]);
+        fringe_verts.push(([curr[0] - 0.5 * normal[0], curr[1] - 0.5 * normal[1]], false));
+        fringe_verts.push(([curr[0] + 0.5 * normal[0], curr[1] + 0.5 * normal[1]], true));
+    }
+    for i in 1..verts.len().saturating_sub(1) {
+        indices.extend_from_slice(&[0, (2*i) as u16, (2*i + 2) as u16]);
+    }
+    for i in 0..verts.len() {
+        indices.extend_from_slice(&[
+            (2*i) as u16, (2*i + 1) as u16, (2*((i+1)%verts.len()) + 1) as u16,
+            (2*i) as u16, (2*((i+1)%verts.len()) + 1) as u16, (2*((i+1)%verts.len())) as u16,
+        ]);
+    }
+    (fringe_verts, indices)
+}
+
+// A set of equally sized atlas pages, each with its own texture. Pages are added whenever
+// a glyph does not fit into any existing page, even after evicting unused rows.
+struct GlyphAtlas {
+    format: TexFormat,
+    pages: Vec<AtlasPage>,
+    // copies of the bitmaps of the glyphs in the pages, if kept
+    bitmaps: Option<HashMap<GlyphId, (u32, u32, Vec<u8>)>>,
+    uploads: usize,
+    upload_time: Duration,
+}
+
+struct AtlasPage {
+    atlas: Atlas,
+    tex: TexId,
+}
+
+impl GlyphAtlas {
+    fn new(renderer: &mut Renderer, format: TexFormat, keep_bitmaps: bool) -> Result<GlyphAtlas, RendererError> {
+        let bitmaps = if keep_bitmaps { Some(HashMap::new()) } else { None };
+        let mut atlas = GlyphAtlas { format, pages: Vec::new(), bitmaps, uploads: 0, upload_time: Duration::default() };
+        atlas.add_page(renderer)?;
+        Ok(atlas)
+    }
+
+    fn add_page(&mut self, renderer: &mut Renderer) -> Result<usize, RendererError> {
+        let size = ATLAS_SIZE as usize;
+        // glyphs are drawn scaled from their raster size and distance fields must be
+        // interpolated, so pages are sampled linearly and glyphs are kept apart by a border
+        let options = TexOptions { filter: TexFilter::Linear, ..TexOptions::default() };
+        let tex = renderer.create_tex(self.format, options, size, size, &vec![0; size * size * self.format.bytes_per_pixel()])?;
+        self.pages.push(AtlasPage { atlas: Atlas::new(ATLAS_SIZE, ATLAS_SIZE), tex });
+        Ok(self.pages.len() - 1)
+    }
+
+    fn update_counter(&mut self) {
+        self.uploads = 0;
+        self.upload_time = Duration::default();
+        for page in self.pages.iter_mut() {
+            page.atlas.update_counter();
+        }
+    }
+
+    fn get_cached(&mut self, glyph_id: GlyphId) -> Option<(usize, Rect)> {
+        for (i, page) in self.pages.iter_mut().enumerate() {
+            if let Some(rect) = page.atlas.get_cached(glyph_id) {
+                return Some((i, inset(rect)));
+            }
+        }
+        None
+    }
+
+    // Rasterizes and uploads a glyph if it is not already cached.
+    fn get_or_insert<F: FnOnce() -> Option<(u32, u32, Vec<u8>)>>(&mut self, renderer: &mut Renderer, glyph_id: GlyphId, rasterize: F) -> Option<(usize, Rect)> {
+        if let Some(entry) = self.get_cached(glyph_id) {
+            return Some(entry);
+        }
+        let start = Instant::now();
+        let (width, height, data) = rasterize()?;
+        let entry = self.insert_data(renderer, glyph_id, width, height, &data);
+        self.upload_time += start.elapsed();
+        entry
+    }
+
+    fn insert_data(&mut self, renderer: &mut Renderer, glyph_id: GlyphId, width: u32, height: u32, data: &[u8]) -> Option<(usize, Rect)> {
+        let evictions: usize = self.pages.iter().map(|page| page.atlas.evictions).sum();
+        let (page, rect) = self.insert(renderer, glyph_id, width + 2 * GLYPH_BORDER, height + 2 * GLYPH_BORDER)?;
+        // the border is uploaded too, as the space may still hold an evicted glyph
+        let bordered = add_border(width, height, data, self.format.bytes_per_pixel());
+        if renderer.update_tex(self.pages[page].tex, rect.x as usize, rect.y as usize, rect.w as usize, rect.h as usize, &bordered).is_err() {
+            // don't leave an entry pointing at whatever the texture held there before
+            self.pages[page].atlas.remove(glyph_id);
+            return None;
+        }
+        if let Some(bitmaps) = self.bitmaps.as_mut() {
+            // copies of evicted glyphs are dropped with them
+            if self.pages.iter().map(|page| page.atlas.evictions).sum::<usize>() != evictions {
+                let pages = &self.pages;
+                bitmaps.retain(|id, _| pages.iter().any(|page| page.atlas.map.contains_key(id)));
+            }
+            bitmaps.insert(glyph_id, (width, height, data.to_vec()));
+        }
+        Some((page, inset(rect)))
+    }
+
+    fn remove_font(&mut self, font: FontId) {
+        for page in self.pages.iter_mut() {
+            let glyphs: Vec<GlyphId> = page.atlas.map.keys().filter(|id| id.font == font).cloned().collect();
+            for glyph_id in glyphs {
+                page.atlas.remove(glyph_id);
+            }
+        }
+        if let Some(bitmaps) = self.bitmaps.as_mut() {
+            bitmaps.retain(|id, _| id.font != font);
+        }
+    }
+
+    // Reserves space for a glyph and its border.
+    fn insert(&mut self, renderer: &mut Renderer, glyph_id: GlyphId, width: u32, height: u32) -> Option<(usize, Rect)> {
+        if width > ATLAS_SIZE || height > ATLAS_SIZE { return None; }
+        self.uploads += 1;
+        // prefer free space on any page over evicting glyphs
+        for &evict in [false, true].iter() {
+            for (i, page) in self.pages.iter_mut().enumerate() {
+                if let Some(rect) = page.atlas.insert(glyph_id, width, height, evict) {
+                    return Some((i, rect));
+                }
+            }
+        }
+        // no glyph can be cached once texture memory runs out
+        let page = self.add_page(renderer).ok()?;
+        self.pages[page].atlas.insert(glyph_id, width, height, false).map(|rect| (page, rect))
+    }
+}
+
+// The glyph within an atlas entry, without its border.
+fn inset(rect: Rect) -> Rect {
+    Rect { x: rect.x + GLYPH_BORDER, y: rect.y + GLYPH_BORDER, w: rect.w - 2 * GLYPH_BORDER, h: rect.h - 2 * GLYPH_BORDER }
+}
+
+// Surrounds a bitmap with transparent texels, so that linear sampling at the edges of a
+// glyph doesn't pick up its neighbours.
+fn add_border(width: u32, height: u32, data: &[u8], bpp: usize) -> Vec<u8> {
+    let (width, height, border) = (width as usize, height as usize, GLYPH_BORDER as usize);
+    let stride = (width + 2 * border) * bpp;
+    let mut bordered = vec![0; stride * (height + 2 * border)];
+    for (y, row) in data.chunks(width * bpp).take(height).enumerate() {
+        let start = (y + border) * stride + border * bpp;
+        bordered[start..start + row.len()].copy_from_slice(row);
+    }
+    bordered
 }
 
 struct Atlas {
@@ -316,6 +1611,7 @@ struct Atlas {
     next_y: u32,
     map: std::collections::HashMap<GlyphId, Entry>,
     counter: usize,
+    evictions: usize,
 }
 
 struct Row {
@@ -356,11 +1652,17 @@ impl Atlas {
             next_y: 0,
             map: std::collections::HashMap::new(),
             counter: 0,
+            evictions: 0,
         }
     }
 
+    fn used_area(&self) -> u32 {
+        self.rows.iter().flat_map(|row| row.glyphs.iter()).map(|glyph| glyph.width * glyph.height).sum()
+    }
+
     fn update_counter(&mut self) {
         self.counter += 1;
+        self.evictions = 0;
     }
 
     fn get_cached(&mut self, glyph_id: GlyphId) -> Option<Rect> {
@@ -374,10 +1676,10 @@ impl Atlas {
         }
     }
 
-    fn insert(&mut self, glyph_id: GlyphId, width: u32, height: u32) -> Option<Rect> {
+    fn insert(&mut self, glyph_id: GlyphId, width: u32, height: u32, evict: bool) -> Option<Rect> {
         if width > self.width || height > self.height { return None; }
 
-        let row_index = self.find_row(width, height);
+        let row_index = self.find_row(width, height, evict);
         if row_index.is_none() { return None; }
         let row_index = row_index.unwrap();
 
@@ -397,8 +1699,19 @@ impl Atlas {
         Some(Rect { x, y: row.y, w: width, h: height })
     }
 
-    fn find_row(&mut self, width: u32, height: u32) -> Option<usize> {
-        let row_height = nearest_pow_2(height);
+    fn remove(&mut self, glyph_id: GlyphId) {
+        if let Some(Entry { row, glyph }) = self.map.remove(&glyph_id) {
+            let row = self.rows.get_mut(row).unwrap();
+            let glyph = row.glyphs.remove(glyph).unwrap();
+            // the space can only be reused if nothing was placed after the glyph
+            if glyph.x + glyph.width == row.next_x {
+                row.next_x = glyph.x;
+            }
+        }
+    }
+
+    fn find_row(&mut self, width: u32, height: u32, evict: bool) -> Option<usize> {
+        let row_height = nearest_pow_2(height.max(1));
         // this logic is to ensure that the search finds the first of a sequence of equal elements
         let mut index = self.rows_by_height
             .binary_search_by_key(&(2 * row_height - 1), |row| 2 * self.rows.get(*row).unwrap().height)
@@ -425,8 +1738,10 @@ impl Atlas {
             return Some(row_index);
         }
         // need to overwrite some rows
-        if let Some(row_index) = self.try_overwrite_rows(row_height) {
-            return Some(row_index);
+        if evict {
+            if let Some(row_index) = self.try_overwrite_rows(row_height) {
+                return Some(row_index);
+            }
         }
         None
     }
@@ -442,51 +1757,53 @@ impl Atlas {
         }
     }
 
+    // Evicts the least recently used run of adjacent rows that is tall enough for a row of
+    // the given height. Rows used during the current frame are never evicted.
     fn try_overwrite_rows(&mut self, row_height: u32) -> Option<usize> {
+        // rows tile the atlas from the top down to next_y without gaps
         let mut rows_by_y = self.rows_by_height.clone();
         rows_by_y.sort_by_key(|row| self.rows.get(*row).unwrap().y);
-        let mut best_i = 0;
-        let mut best_height = 0;
-        let mut best_num_rows = 0;
-        let mut best_last_used = self.counter as f32 + 1.0;
+
+        // (first row, number of rows, total height, most recent use)
+        let mut best: Option<(usize, usize, u32, usize)> = None;
         for i in 0..rows_by_y.len() {
             let mut num_rows = 0;
             let mut rows_height = 0;
-            let mut last_used_sum = 0;
-            while row_height > rows_height && i + num_rows < rows_by_y.len() {
-                let row = self.rows.get(rows_by_y[i]).unwrap();
-                if row.last_used == self.counter { continue; }
-                num_rows += 1;
+            let mut last_used = 0;
+            while rows_height < row_height && i + num_rows < rows_by_y.len() {
+                let row = self.rows.get(rows_by_y[i + num_rows]).unwrap();
+                if row.last_used == self.counter { break; }
                 rows_height += row.height;
-                last_used_sum += row.last_used;
-            }
-            if row_height <= rows_height {
-                let last_used_avg = last_used_sum as f32 / num_rows as f32;
-                if last_used_avg < best_last_used {
-                    best_i = i;
-                    best_height = rows_height;
-                    best_num_rows = num_rows;
-                    best_last_used = last_used_avg;
-                }
+                last_used = last_used.max(row.last_used);
+                num_rows += 1;
             }
-        }
-        if best_height > 0 {
-            let y = self.rows.get(rows_by_y[best_i]).unwrap().y;
-            for row_index in &rows_by_y[best_i..(best_i + best_num_rows)] {
-                self.rows_by_height.remove(*row_index);
-                let row = self.rows.remove(*row_index).unwrap();
-                for glyph in row.glyphs.iter() {
-                    self.map.remove(&glyph.glyph_id);
+            if rows_height >= row_height {
+                // least recently used first, then the tightest fit
+                let better = best.map_or(true, |(_, best_num_rows, best_height, best_last_used)| {
+                    (last_used, rows_height, num_rows) < (best_last_used, best_height, best_num_rows)
+                });
+                if better {
+                    best = Some((i, num_rows, rows_height, last_used));
                 }
             }
-            let row_index = self.add_row(Row::new(y, row_height));
-            if best_height > row_height {
-                self.add_row(Row::new(y + row_height, best_height - row_height));
+        }
+
+        let (i, num_rows, rows_height, _) = best?;
+        let y = self.rows.get(rows_by_y[i]).unwrap().y;
+        for row_index in &rows_by_y[i..i + num_rows] {
+            let position = self.rows_by_height.iter().position(|row| row == row_index).unwrap();
+            self.rows_by_height.remove(position);
+            let row = self.rows.remove(*row_index).unwrap();
+            for glyph in row.glyphs.iter() {
+                self.map.remove(&glyph.glyph_id);
+                self.evictions += 1;
             }
-            Some(row_index)
-        } else {
-            None
         }
+        let row_index = self.add_row(Row::new(y, row_height));
+        if rows_height > row_height {
+            self.add_row(Row::new(y + row_height, rows_height - row_height));
+        }
+        Some(row_index)
     }
 
     fn add_row(&mut self, row: Row) -> usize {
@@ -514,3 +1831,212 @@ fn nearest_pow_2(x: u32) -> u32 {
     x += 1;
     x
 }
+
+#[cfg(test)]
+fn test_glyph(glyph: u16) -> GlyphId {
+    GlyphId { font: 0, scale: 16, glyph, foreground: [0; 3] }
+}
+
+// Checks that live glyphs lie within the atlas without overlapping and that the rows tile
+// the atlas from the top.
+#[cfg(test)]
+fn check_atlas(atlas: &Atlas) {
+    let rects: Vec<Rect> = atlas.map.values().map(|&Entry { row, glyph }| {
+        let row = atlas.rows.get(row).unwrap();
+        let glyph = row.glyphs.get(glyph).unwrap();
+        assert!(glyph.height <= row.height);
+        Rect { x: glyph.x, y: row.y, w: glyph.width, h: glyph.height }
+    }).collect();
+    for (i, a) in rects.iter().enumerate() {
+        assert!(a.x + a.w <= atlas.width && a.y + a.h <= atlas.height);
+        for b in rects[i + 1..].iter() {
+            let disjoint = a.x + a.w <= b.x || b.x + b.w <= a.x || a.y + a.h <= b.y || b.y + b.h <= a.y;
+            assert!(disjoint, "{:?} overlaps {:?}", a, b);
+        }
+    }
+    let mut rows: Vec<&Row> = atlas.rows.iter().collect();
+    rows.sort_by_key(|row| row.y);
+    let mut y = 0;
+    for row in rows {
+        assert_eq!(row.y, y);
+        y += row.height;
+    }
+    assert_eq!(y, atlas.next_y);
+    assert_eq!(atlas.rows_by_height.len(), atlas.rows.iter().count());
+}
+
+#[test]
+fn test_atlas_fill() {
+    let mut atlas = Atlas::new(64, 64);
+    atlas.update_counter();
+    let mut inserted = Vec::new();
+    for glyph in 0..64 {
+        if let Some(rect) = atlas.insert(test_glyph(glyph), 10, 7, false) {
+            inserted.push((glyph, rect));
+        }
+    }
+    // 6 glyphs per row, 8 rows of height 8
+    assert_eq!(inserted.len(), 48);
+    check_atlas(&atlas);
+    for (glyph, rect) in inserted {
+        let cached = atlas.get_cached(test_glyph(glyph)).unwrap();
+        assert_eq!((cached.x, cached.y, cached.w, cached.h), (rect.x, rect.y, rect.w, rect.h));
+    }
+}
+
+#[test]
+fn test_atlas_evict() {
+    let mut atlas = Atlas::new(64, 64);
+    atlas.update_counter();
+    for glyph in 0..48 {
+        atlas.insert(test_glyph(glyph), 10, 7, false).unwrap();
+    }
+    assert!(atlas.insert(test_glyph(100), 10, 7, false).is_none());
+    // every row was used this frame, so nothing can be evicted
+    assert!(atlas.insert(test_glyph(100), 10, 7, true).is_none());
+
+    atlas.update_counter();
+    // keep the first row alive
+    for glyph in 0..6 {
+        atlas.get_cached(test_glyph(glyph)).unwrap();
+    }
+    atlas.insert(test_glyph(100), 10, 14, true).unwrap();
+    check_atlas(&atlas);
+    for glyph in 0..6 {
+        assert!(atlas.get_cached(test_glyph(glyph)).is_some());
+    }
+    // the two rows following the live one were the least recently used
+    for glyph in 6..18 {
+        assert!(atlas.get_cached(test_glyph(glyph)).is_none());
+    }
+    assert_eq!(atlas.evictions, 12);
+    assert_eq!(atlas.used_area(), 36 * 10 * 7 + 10 * 14);
+    assert!(atlas.get_cached(test_glyph(100)).is_some());
+}
+
+#[test]
+fn test_atlas_remove() {
+    let mut atlas = Atlas::new(64, 64);
+    let first = atlas.insert(test_glyph(0), 10, 7, false).unwrap();
+    atlas.remove(test_glyph(0));
+    assert!(atlas.get_cached(test_glyph(0)).is_none());
+    let second = atlas.insert(test_glyph(1), 10, 7, false).unwrap();
+    assert_eq!((second.x, second.y), (first.x, first.y));
+    check_atlas(&atlas);
+}
+
+#[test]
+fn test_atlas_lru() {
+    let mut atlas = Atlas::new(64, 32);
+    atlas.update_counter();
+    for glyph in 0..4 {
+        atlas.insert(test_glyph(glyph), 64, 8, false).unwrap();
+    }
+    // touch rows in the order 2, 0, 3, 1 over the next frames
+    for &glyph in [2, 0, 3, 1].iter() {
+        atlas.update_counter();
+        atlas.get_cached(test_glyph(glyph)).unwrap();
+    }
+    atlas.update_counter();
+    for &(glyph, evicted) in [(10, 2), (11, 0), (12, 3)].iter() {
+        atlas.insert(test_glyph(glyph), 64, 8, true).unwrap();
+        assert!(atlas.get_cached(test_glyph(evicted)).is_none());
+        check_atlas(&atlas);
+    }
+    assert!(atlas.get_cached(test_glyph(1)).is_some());
+}
+
+#[test]
+fn test_atlas_cycles() {
+    let mut atlas = Atlas::new(256, 256);
+    let mut seed: u32 = 1;
+    let mut random = move || {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        (seed >> 16) & 0x7fff
+    };
+    for _ in 0..200 {
+        atlas.update_counter();
+        let mut frame = Vec::new();
+        for _ in 0..10 {
+            let glyph = (random() % 300) as u16;
+            // sizes are derived from the glyph so that cached entries stay consistent
+            let (width, height) = (1 + glyph as u32 % 23, 1 + glyph as u32 * 7 % 29);
+            if atlas.get_cached(test_glyph(glyph)).is_none() {
+                atlas.insert(test_glyph(glyph), width, height, true).unwrap();
+            }
+            frame.push(glyph);
+            check_atlas(&atlas);
+        }
+        // nothing used during the frame may have been evicted
+        for glyph in frame {
+            assert!(atlas.map.contains_key(&test_glyph(glyph)));
+        }
+    }
+}
+
+#[test]
+fn test_damage() {
+    let screen = Bounds { x: 0.0, y: 0.0, width: 100.0, height: 100.0 };
+    let a = Bounds { x: 0.0, y: 0.0, width: 10.0, height: 10.0 };
+    let b = Bounds { x: 5.0, y: 5.0, width: 10.0, height: 10.0 };
+    let c = Bounds { x: 50.0, y: 50.0, width: 10.0, height: 10.0 };
+    assert!(damage(&[(1, a), (2, c)], &[(1, a), (2, c)], screen).is_empty());
+    // an item moving from a to b damages both, merged as they overlap
+    assert_eq!(damage(&[(1, a), (2, c)], &[(3, b), (2, c)], screen), vec![Bounds { x: 0.0, y: 0.0, width: 15.0, height: 15.0 }]);
+    // damage is clipped to the screen
+    let d = Bounds { x: 95.0, y: 95.0, width: 10.0, height: 10.0 };
+    assert_eq!(damage(&[], &[(4, d)], screen), vec![Bounds { x: 95.0, y: 95.0, width: 5.0, height: 5.0 }]);
+}
+
+#[test]
+fn test_path_cache() {
+    let rect = |x: f32, y: f32| vec![
+        PathSegment([x, y], SegmentType::Line),
+        PathSegment([x, y + 10.0], SegmentType::Line),
+        PathSegment([x + 10.0, y + 10.0], SegmentType::Line),
+        PathSegment([x + 10.0, y], SegmentType::Line),
+    ];
+    let mut cache = PathCache::new();
+    cache.update_counter();
+    let (origin, entry) = cache.get(&rect(0.1, 0.2));
+    assert_eq!(origin, [0.1, 0.2]);
+    assert_eq!(entry.verts.len(), 8);
+    // a translated path reuses the same entry
+    let (origin, _) = cache.get(&rect(1000.7, 33.3));
+    assert_eq!(origin, [1000.7, 33.3]);
+    assert_eq!(cache.entries.len(), 1);
+    cache.get(&[PathSegment([0.0, 0.0], SegmentType::Arc(5.0, 0.0, 2.0 * PI))]);
+    assert_eq!(cache.entries.len(), 2);
+    for _ in 0..=PATH_CACHE_FRAMES {
+        cache.update_counter();
+    }
+    assert!(cache.entries.is_empty());
+}
+
+#[test]
+fn test_add_border() {
+    let bordered = add_border(2, 1, &[1, 2, 3, 4], 2);
+    assert_eq!(bordered, [
+        0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 1, 2, 3, 4, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0,
+    ]);
+    let rect = inset(Rect { x: 10, y: 20, w: 4, h: 3 });
+    assert_eq!((rect.x, rect.y, rect.w, rect.h), (11, 21, 2, 1));
+}
+
+#[test]
+fn test_edge_fringe() {
+    // the same square wound both ways gets its fringe on the outside
+    let square = [[10.0, 10.0], [20.0, 10.0], [20.0, 20.0], [10.0, 20.0]];
+    let reversed = [[10.0, 10.0], [10.0, 20.0], [20.0, 20.0], [20.0, 10.0]];
+    for points in [&square, &reversed].iter() {
+        let (verts, indices) = edge_fringe(&points[..], [1.0; 4], 100.0, 100.0);
+        assert_eq!((verts.len(), indices.len()), (16, 24));
+        for vert in verts.iter() {
+            let (x, y) = (50.0 * (vert.pos[0] + 1.0), 50.0 * (1.0 - vert.pos[1]));
+            let inside = x > 9.9 && x < 20.1 && y > 9.9 && y < 20.1;
+            assert_eq!(inside, vert.col[3] == 1.0, "{:?}", (x, y));
+        }
+    }
+}
@@ -3,6 +3,12 @@ mod ui;
 mod graphics;
 mod render;
 mod alloc;
+mod font;
+mod glyph_cache;
+mod sdf;
+mod subpixel;
+mod color;
+mod png;
 
 use alloc::*;
 use graphics::*;
@@ -28,52 +34,56 @@ fn main() {
 
     let dpi_factor = gl_window.get_hidpi_factor();
 
-    let mut ui = UI::new(dpi_factor as f32);
-    let font = ui.graphics().add_font(include_bytes!("../res/sawarabi-gothic-medium.ttf"));
+    let mut ui = match UI::new(dpi_factor as f32) {
+        Ok(ui) => ui,
+        Err(error) => {
+            eprintln!("{}", error);
+            return;
+        }
+    };
+    let font = ui.graphics().add_font(&include_bytes!("../res/sawarabi-gothic-medium.ttf")[..]).unwrap();
+    ui.graphics().partial_redraw(true);
 
     const FRAME: std::time::Duration = std::time::Duration::from_micros(1_000_000 / 60);
-    let mut frames: [u32; 100] = [0; 100];
-    let mut i: usize = 0;
-    let mut sum: u32 = 0;
+    // shown with a frame's delay, as a frame's stats are only known once it is drawn
+    let mut stats = FrameStats::default();
+    // toggled with F3. The overlay keeps requesting frames while shown, so it is off by default
+    let mut show_stats = false;
     let mut running = true;
-    let mut now = std::time::Instant::now();
     while running {
-        let elapsed = now.elapsed();
-        now = std::time::Instant::now();
-        sum -= frames[i];
-        frames[i] = elapsed.as_secs() as u32 * 1000 + elapsed.subsec_millis();
-        sum += frames[i];
-        i = (i + 1) % frames.len();
-        let fps = 100000.0 / (sum as f32);
-        let fps_text = fps.round().to_string();
+        let now = std::time::Instant::now();
 
         let size = gl_window.get_inner_size().unwrap();
 
-        // let mut graphics = ui.graphics();
-        // graphics.clear(Color::rgba(0.1, 0.15, 0.2, 1.0));
-        // graphics.text([0.0, 0.0], "Jackdaws love my big sphinx of quartz.", font, 14, Color::rgba(0.8, 0.8, 0.8, 1.0));
-        // graphics.text([700.0, 580.0], &fps_text, font, 14, Color::rgba(1.0, 1.0, 1.0, 1.0));
-        // graphics.round_rect_fill([100.0, 100.0], [100.0, 100.0], 5.0, Color::rgba(0.8, 0.5, 0.0, 1.0));
-        // graphics.circle_fill([225.0, 225.0], 101.0, Color::rgba(0.5, 0.25, 1.0, 0.75));
-        // graphics.circle_fill([300.0, 100.0], 150.0, Color::rgba(0.0, 0.5, 1.0, 0.5));
-        // graphics.draw(size.width as f32, size.height as f32);
+        if ui.needs_frame() {
+            // let mut graphics = ui.graphics();
+            // graphics.clear(Color::rgba(0.1, 0.15, 0.2, 1.0));
+            // graphics.text([0.0, 0.0], "Jackdaws love my big sphinx of quartz.", font, 14.0, Color::rgba(0.8, 0.8, 0.8, 1.0));
+            // graphics.round_rect_fill([100.0, 100.0], [100.0, 100.0], 5.0, Color::rgba(0.8, 0.5, 0.0, 1.0));
+            // graphics.circle_fill([225.0, 225.0], 101.0, Color::rgba(0.5, 0.25, 1.0, 0.75));
+            // graphics.circle_fill([300.0, 100.0], 150.0, Color::rgba(0.0, 0.5, 1.0, 0.5));
+            // graphics.draw(size.width as f32, size.height as f32);
 
-        ui.graphics().clear(Color::rgba(0.1, 0.15, 0.2, 1.0));
-        let xs = [1, 2, 3];
-        let a = Arena::with_capacity(1024);
-        let tree = Padding::uniform(&a, 20.0, Row::new(&a, 10.0, &[
-            Row::new(&a, 10.0, &[
-                Text::new(&a, &fps_text, font, 14, Color::rgba(1.0, 1.0, 1.0, 1.0)),
-                Text::new(&a, "2", font, 14, Color::rgba(1.0, 1.0, 1.0, 1.0)),
-            ]),
-            Row::new(&a, 10.0, &xs.iter().map(|x|
-                Text::new(&a, a.alloc_str(&x.to_string()), font, 14, Color::rgba(1.0, 1.0, 1.0, 1.0)) as &dyn Widget
-            ).collect::<Vec<&dyn Widget>>()),
-            Button::new(&a, Text::new(&a, "button", font, 14, Color::rgba(1.0, 1.0, 1.0, 1.0))),
-        ]));
-        ui.run(size.width as f32, size.height as f32, tree);
-
-        gl_window.swap_buffers().unwrap();
+            ui.graphics().clear(Color::rgba(0.1, 0.15, 0.2, 1.0));
+            let xs = [1, 2, 3];
+            let a = Arena::with_capacity(1024);
+            let mut first: Vec<&dyn Widget> = Vec::new();
+            if show_stats {
+                first.push(StatsOverlay::new(&a, &stats, font, 14.0));
+            }
+            first.push(Text::new(&a, "2", font, 14.0, Color::rgba(1.0, 1.0, 1.0, 1.0)));
+            let tree = Padding::uniform(&a, 20.0, Flex::new(&a, Direction::Row, &[
+                FlexItem::new(Row::new(&a, 10.0, &first)),
+                FlexItem::new(Row::new(&a, 10.0, &xs.iter().map(|x|
+                    Text::new(&a, a.alloc_str(&x.to_string()), font, 14.0, Color::rgba(1.0, 1.0, 1.0, 1.0)) as &dyn Widget
+                ).collect::<Vec<&dyn Widget>>())).grow(1.0),
+                FlexItem::new(Button::new(&a, Text::new(&a, "button", font, 14.0, Color::rgba(1.0, 1.0, 1.0, 1.0)))),
+            ]).align(Align::Center).gap(10.0));
+            stats = ui.run(size.width as f32, size.height as f32, tree);
+            if stats.changed {
+                gl_window.swap_buffers().unwrap();
+            }
+        }
 
         events_loop.poll_events(|ev| {
             match ev {
@@ -81,12 +91,18 @@ fn main() {
                     glutin::WindowEvent::CloseRequested => running = false,
                     glutin::WindowEvent::Resized(logical_size) => {
                         gl_window.resize(logical_size.to_physical(dpi_factor));
+                        ui.redraw();
                     }
+                    glutin::WindowEvent::Refresh => ui.redraw(),
                     glutin::WindowEvent::ReceivedCharacter(char) => {
                         ui.input(Input::Char(char));
                     }
                     glutin::WindowEvent::KeyboardInput { input: glutin::KeyboardInput { state, virtual_keycode, modifiers, .. }, .. } => {
                         ui.modifiers(glutin_modifiers(modifiers));
+                        if state == glutin::ElementState::Pressed && virtual_keycode == Some(glutin::VirtualKeyCode::F3) {
+                            show_stats = !show_stats;
+                            ui.redraw();
+                        }
                         if let Some(key) = virtual_keycode.and_then(glutin_key) {
                             match state {
                                 glutin::ElementState::Pressed => ui.input(Input::KeyDown(key)),
@@ -0,0 +1,386 @@
+// A minimal PNG decoder for the bitmaps embedded in color fonts. Interlaced images are not
+// supported. Returns the width, height and straight alpha RGBA8 pixels of the image.
+pub fn decode_png(data: &[u8]) -> Option<(usize, usize, Vec<u8>)> {
+    if data.get(0..8)? != b"\x89PNG\r\n\x1a\n" { return None; }
+    let (width, height) = png_size(data)?;
+
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut transparency: &[u8] = &[];
+    let mut compressed = Vec::new();
+    let mut offset = 8;
+    while offset + 8 <= data.len() {
+        let length = read_u32(data, offset)? as usize;
+        let kind = data.get(offset + 4..offset + 8)?;
+        let chunk = data.get(offset + 8..offset + 8 + length)?;
+        match kind {
+            b"IHDR" => header = Some((*chunk.get(8)?, *chunk.get(9)?, *chunk.get(12)?)),
+            b"PLTE" => palette = chunk,
+            b"tRNS" => transparency = chunk,
+            b"IDAT" => compressed.extend_from_slice(chunk),
+            b"IEND" => break,
+            _ => {}
+        }
+        // skip the chunk data and its crc
+        offset += 12 + length;
+    }
+
+    let (depth, color_type, interlace) = header?;
+    if interlace != 0 { return None; }
+    let channels = match (color_type, depth) {
+        (0, 1) | (0, 2) | (0, 4) | (0, 8) | (0, 16) => 1,
+        (3, 1) | (3, 2) | (3, 4) | (3, 8) => 1,
+        (4, 8) | (4, 16) => 2,
+        (2, 8) | (2, 16) => 3,
+        (6, 8) | (6, 16) => 4,
+        _ => return None,
+    };
+    let depth = depth as usize;
+    let stride = (width.checked_mul(channels * depth)? + 7) / 8;
+    let bpp = ((channels * depth) / 8).max(1);
+    let size = (stride + 1).checked_mul(height)?;
+
+    let mut pixels = inflate(&compressed, size)?;
+    if pixels.len() < size { return None; }
+    unfilter(&mut pixels, stride, height, bpp)?;
+
+    // reads sample `i` of a row, scaled to 8 bits unless it is a palette index
+    let sample = |row: &[u8], i: usize| -> u8 {
+        match depth {
+            16 => row[2 * i],
+            8 => row[i],
+            _ => {
+                let bit = i * depth;
+                let value = (row[bit / 8] >> (8 - depth - bit % 8)) & ((1 << depth) - 1) as u8;
+                if color_type == 3 { value } else { value * (255 / ((1 << depth) - 1)) as u8 }
+            }
+        }
+    };
+
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        let row = &pixels[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        for x in 0..width {
+            let pixel = match color_type {
+                0 => {
+                    let gray = sample(row, x);
+                    // a single transparent gray level may be given in tRNS
+                    let alpha = if transparency.len() >= 2 && depth == 8 && transparency[1] == gray { 0 } else { 255 };
+                    [gray, gray, gray, alpha]
+                }
+                2 => {
+                    let (r, g, b) = (sample(row, 3 * x), sample(row, 3 * x + 1), sample(row, 3 * x + 2));
+                    let key = transparency.len() >= 6 && depth == 8 && [transparency[1], transparency[3], transparency[5]] == [r, g, b];
+                    [r, g, b, if key { 0 } else { 255 }]
+                }
+                3 => {
+                    let index = sample(row, x) as usize;
+                    let color = palette.get(3 * index..3 * index + 3)?;
+                    [color[0], color[1], color[2], *transparency.get(index).unwrap_or(&255)]
+                }
+                4 => {
+                    let gray = sample(row, 2 * x);
+                    [gray, gray, gray, sample(row, 2 * x + 1)]
+                }
+                _ => [sample(row, 4 * x), sample(row, 4 * x + 1), sample(row, 4 * x + 2), sample(row, 4 * x + 3)],
+            };
+            rgba.extend_from_slice(&pixel);
+        }
+    }
+    Some((width, height, rgba))
+}
+
+// Reads the dimensions of a PNG image from its header without decoding it.
+pub fn png_size(data: &[u8]) -> Option<(usize, usize)> {
+    if data.get(12..16)? != b"IHDR" { return None; }
+    Some((read_u32(data, 16)? as usize, read_u32(data, 20)? as usize))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|b| (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32)
+}
+
+// Reverses the per-row filters in place. Each row is preceded by its filter type.
+fn unfilter(pixels: &mut [u8], stride: usize, height: usize, bpp: usize) -> Option<()> {
+    for y in 0..height {
+        let start = y * (stride + 1);
+        let filter = pixels[start];
+        for i in 0..stride {
+            let pos = start + 1 + i;
+            let left = if i >= bpp { pixels[pos - bpp] } else { 0 };
+            let up = if y > 0 { pixels[pos - stride - 1] } else { 0 };
+            let up_left = if y > 0 && i >= bpp { pixels[pos - stride - 1 - bpp] } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return None,
+            };
+            pixels[pos] = pixels[pos].wrapping_add(predicted);
+        }
+    }
+    Some(())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// order in which code length code lengths are stored in a dynamic block header
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// Decompresses a zlib stream, failing once the output would exceed `limit` bytes. The
+// checksum is not verified.
+fn inflate(data: &[u8], limit: usize) -> Option<Vec<u8>> {
+    if data.len() < 2 || data[0] & 0x0f != 8 || ((data[0] as u16) << 8 | data[1] as u16) % 31 != 0 { return None; }
+    let mut bits = BitReader { data, pos: 2, bit: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.bits(1)?;
+        match bits.bits(2)? {
+            0 => {
+                // stored blocks start on a byte boundary
+                if bits.bit > 0 { bits.pos += 1; bits.bit = 0; }
+                let length = *data.get(bits.pos)? as usize | (*data.get(bits.pos + 1)? as usize) << 8;
+                if out.len() + length > limit { return None; }
+                out.extend_from_slice(data.get(bits.pos + 4..bits.pos + 4 + length)?);
+                bits.pos += 4 + length;
+            }
+            1 => {
+                let mut lengths = [0; 288 + 32];
+                for (i, length) in lengths.iter_mut().enumerate() {
+                    *length = match i { 0..=143 => 8, 144..=255 => 9, 256..=279 => 7, 280..=287 => 8, _ => 5 };
+                }
+                let literals = Huffman::new(&lengths[..288]);
+                let distances = Huffman::new(&lengths[288..]);
+                inflate_block(&mut bits, &mut out, &literals, &distances, limit)?;
+            }
+            2 => {
+                let num_literals = bits.bits(5)? as usize + 257;
+                let num_distances = bits.bits(5)? as usize + 1;
+                let num_codes = bits.bits(4)? as usize + 4;
+                let mut code_lengths = [0; 19];
+                for &i in CODE_LENGTH_ORDER[..num_codes].iter() {
+                    code_lengths[i] = bits.bits(3)? as u8;
+                }
+                let code_lengths = Huffman::new(&code_lengths);
+                let mut lengths = Vec::with_capacity(num_literals + num_distances);
+                while lengths.len() < num_literals + num_distances {
+                    match bits.decode(&code_lengths)? {
+                        symbol @ 0..=15 => lengths.push(symbol as u8),
+                        16 => {
+                            let previous = *lengths.last()?;
+                            for _ in 0..3 + bits.bits(2)? { lengths.push(previous); }
+                        }
+                        17 => for _ in 0..3 + bits.bits(3)? { lengths.push(0); },
+                        _ => for _ in 0..11 + bits.bits(7)? { lengths.push(0); },
+                    }
+                }
+                if lengths.len() > num_literals + num_distances { return None; }
+                let literals = Huffman::new(&lengths[..num_literals]);
+                let distances = Huffman::new(&lengths[num_literals..]);
+                inflate_block(&mut bits, &mut out, &literals, &distances, limit)?;
+            }
+            _ => return None,
+        }
+        if last == 1 { break; }
+    }
+    Some(out)
+}
+
+fn inflate_block(bits: &mut BitReader, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman, limit: usize) -> Option<()> {
+    loop {
+        let symbol = bits.decode(literals)? as usize;
+        if symbol != 256 && out.len() >= limit { return None; }
+        if symbol < 256 {
+            out.push(symbol as u8);
+        } else if symbol == 256 {
+            return Some(());
+        } else {
+            let symbol = symbol - 257;
+            let length = *LENGTH_BASE.get(symbol)? as usize + bits.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+            let symbol = bits.decode(distances)? as usize;
+            let distance = *DIST_BASE.get(symbol)? as usize + bits.bits(DIST_EXTRA[symbol] as u32)? as usize;
+            if distance > out.len() || out.len() + length > limit { return None; }
+            // copies may overlap the bytes they produce
+            for _ in 0..length {
+                out.push(out[out.len() - distance]);
+            }
+        }
+    }
+}
+
+// Canonical Huffman code, stored as the number of codes of each length and the symbols in
+// code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols = Vec::with_capacity(lengths.len());
+        for length in 1..16 {
+            for (symbol, &l) in lengths.iter().enumerate() {
+                if l as usize == length {
+                    symbols.push(symbol as u16);
+                }
+            }
+        }
+        Huffman { counts, symbols }
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+    // Reads `count` bits, least significant first.
+    fn bits(&mut self, count: u32) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = *self.data.get(self.pos)?;
+            value |= ((byte >> self.bit) as u32 & 1) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Some(value)
+    }
+
+    // Huffman codes are packed starting from their most significant bit.
+    fn decode(&mut self, huffman: &Huffman) -> Option<u16> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for length in 1..16 {
+            code |= self.bits(1)? as i32;
+            let count = huffman.counts[length] as i32;
+            if code - first < count {
+                return huffman.symbols.get((index + code - first) as usize).cloned();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+#[test]
+fn test_inflate() {
+    // a stored block
+    assert_eq!(inflate(&[120, 1, 1, 3, 0, 252, 255, 97, 98, 99, 2, 77, 1, 39], 3).unwrap(), b"abc");
+    // fixed Huffman codes with back references
+    let fixed = [
+        120, 218, 43, 201, 72, 85, 40, 44, 205, 76, 206, 86, 72, 42, 202, 47, 207, 83, 72, 203, 175, 80, 200, 42,
+        205, 45, 40, 86, 200, 47, 75, 45, 82, 40, 1, 74, 231, 36, 86, 85, 42, 164, 228, 167, 235, 128, 121, 104,
+        138, 1, 254, 100, 23, 121,
+    ];
+    assert_eq!(&inflate(&fixed, 1000).unwrap()[..], &b"the quick brown fox jumps over the lazy dog, the quick brown fox"[..]);
+    // dynamic Huffman codes
+    let dynamic = [
+        120, 218, 237, 143, 57, 14, 2, 64, 8, 69, 207, 10, 195, 54, 48, 48, 220, 191, 18, 141, 133, 177, 183, 179,
+        34, 228, 47, 249, 15, 144, 29, 156, 17, 222, 87, 212, 175, 92, 63, 113, 46, 245, 38, 96, 217, 229, 171, 250,
+        182, 37, 97, 122, 93, 60, 11, 78, 101, 73, 104, 119, 2, 250, 13, 34, 225, 40, 163, 28, 157, 20, 82, 196,
+        52, 217, 133, 38, 31, 107, 114, 72, 182, 33, 148, 89, 229, 80, 185, 217, 248, 142, 96, 55, 126, 254, 79, 61,
+        96, 219, 211, 159, 43, 24, 166, 215, 57, 213, 68, 18, 116, 124, 153, 100, 21, 60, 189, 113, 29, 33, 187, 53,
+        100, 166, 28, 88, 7, 239, 232, 147, 179, 153, 90, 203, 107, 203, 228, 119, 211, 29, 148, 23, 146, 202, 23, 42,
+        252, 209, 127, 135, 254, 0, 100, 141, 244, 3,
+    ];
+    let expected: Vec<u8> = (0..600u32).map(|i| ((i * i % 251) % 16 + 97) as u8).collect();
+    assert_eq!(inflate(&dynamic, 1000).unwrap(), expected);
+    assert!(inflate(&dynamic[..100], 1000).is_none());
+}
+
+#[test]
+fn test_inflate_corrupt() {
+    // not a deflate stream, then the reserved block type
+    assert!(inflate(&[0, 0, 3, 0], 100).is_none());
+    assert!(inflate(&[120, 1, 7], 100).is_none());
+    // a stored block with its bytes cut off
+    assert!(inflate(&[120, 1, 1, 3, 0, 252, 255, 97], 100).is_none());
+    // a fixed block whose first symbol copies from before the start of the output
+    assert!(inflate(&[120, 1, 3, 2, 0], 100).is_none());
+    // output beyond the limit
+    let stored = [120, 1, 1, 3, 0, 252, 255, 97, 98, 99, 2, 77, 1, 39];
+    assert!(inflate(&stored, 2).is_none());
+    let fixed = [
+        120, 218, 43, 201, 72, 85, 40, 44, 205, 76, 206, 86, 72, 42, 202, 47, 207, 83, 72, 203, 175, 80, 200, 42,
+        205, 45, 40, 86, 200, 47, 75, 45, 82, 40, 1, 74, 231, 36, 86, 85, 42, 164, 228, 167, 235, 128, 121, 104,
+        138, 1, 254, 100, 23, 121,
+    ];
+    assert!(inflate(&fixed, 20).is_none());
+    // every truncation before the unchecked checksum must fail, and bit flips must not panic
+    for len in 0..fixed.len() - 4 {
+        assert!(inflate(&fixed[..len], 1000).is_none());
+    }
+    for i in 0..fixed.len() * 8 {
+        let mut corrupt = fixed;
+        corrupt[i / 8] ^= 1 << (i % 8);
+        inflate(&corrupt, 1000);
+    }
+}
+
+#[test]
+fn test_decode_png() {
+    // 2x2 RGBA with the sub filter on the first row and the up filter on the second
+    let rgba = [
+        137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 2, 0, 0, 0, 2, 8, 6, 0, 0, 0, 114,
+        182, 13, 36, 0, 0, 0, 26, 73, 68, 65, 84, 120, 218, 99, 252, 207, 192, 240, 159, 241, 63, 67, 35, 19, 35,
+        195, 127, 6, 46, 81, 185, 6, 0, 55, 71, 5, 64, 239, 202, 14, 123, 0, 0, 0, 0, 73, 69, 78, 68, 174, 66, 96, 130,
+    ];
+    assert_eq!(png_size(&rgba), Some((2, 2)));
+    let (width, height, pixels) = decode_png(&rgba).unwrap();
+    assert_eq!((width, height), (2, 2));
+    assert_eq!(pixels, vec![255, 0, 0, 255, 0, 255, 0, 128, 0, 0, 255, 255, 10, 20, 30, 0]);
+
+    // 3x1 one bit palette image whose first entry is transparent
+    let palette = [
+        137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 3, 0, 0, 0, 1, 1, 3, 0, 0, 0, 33,
+        46, 134, 247, 0, 0, 0, 6, 80, 76, 84, 69, 0, 0, 0, 255, 255, 255, 165, 217, 159, 221, 0, 0, 0, 1, 116, 82,
+        78, 83, 0, 64, 230, 216, 102, 0, 0, 0, 10, 73, 68, 65, 84, 120, 218, 99, 88, 0, 0, 0, 162, 0, 161, 113, 5,
+        203, 65, 0, 0, 0, 0, 73, 69, 78, 68, 174, 66, 96, 130,
+    ];
+    let (width, height, pixels) = decode_png(&palette).unwrap();
+    assert_eq!((width, height), (3, 1));
+    assert_eq!(pixels, vec![255, 255, 255, 255, 0, 0, 0, 0, 255, 255, 255, 255]);
+}
+
+#[test]
+fn test_decode_png_corrupt() {
+    let rgba = [
+        137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 2, 0, 0, 0, 2, 8, 6, 0, 0, 0, 114,
+        182, 13, 36, 0, 0, 0, 26, 73, 68, 65, 84, 120, 218, 99, 252, 207, 192, 240, 159, 241, 63, 67, 35, 19, 35,
+        195, 127, 6, 46, 81, 185, 6, 0, 55, 71, 5, 64, 239, 202, 14, 123, 0, 0, 0, 0, 73, 69, 78, 68, 174, 66, 96, 130,
+    ];
+    // cut off anywhere before the end of the image data
+    for len in 0..67 {
+        assert!(decode_png(&rgba[..len]).is_none());
+    }
+    // dimensions far larger than the data
+    let mut huge = rgba;
+    huge[16..24].copy_from_slice(&[255; 8]);
+    assert!(decode_png(&huge).is_none());
+    for i in 0..rgba.len() * 8 {
+        let mut corrupt = rgba;
+        corrupt[i / 8] ^= 1 << (i % 8);
+        decode_png(&corrupt);
+    }
+}
@@ -1,12 +1,70 @@
 use std::ffi::{CStr, CString};
-use gl::types::{GLuint, GLint, GLchar, GLenum};
+use std::fmt;
+use std::time::{Duration, Instant};
+use gl::types::{GLuint, GLint, GLchar, GLenum, GLsizei};
 
 use crate::alloc::Slab;
 
-#[derive(Copy, Clone)]
-pub enum TexFormat { RGBA, A }
+// RGBA holds linear color and SRGBA sRGB encoded color, which is decoded when sampled.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TexFormat { RGBA, SRGBA, A, LCD, SDF }
+
+impl TexFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            TexFormat::RGBA | TexFormat::SRGBA => 4,
+            TexFormat::A | TexFormat::SDF => 1,
+            TexFormat::LCD => 3,
+        }
+    }
+}
 pub type TexId = usize;
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TexFilter { Nearest, Linear }
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TexWrap { Clamp, Repeat, MirroredRepeat }
+
+// How a texture is sampled. Mipmaps are regenerated after every update. RGBA and SRGBA
+// textures are stored with premultiplied alpha so that filtering doesn't bleed the color of
+// transparent texels; `premultiplied` says whether the pixels passed in already are.
+#[derive(Copy, Clone, Debug)]
+pub struct TexOptions {
+    pub filter: TexFilter,
+    pub wrap: TexWrap,
+    pub mipmaps: bool,
+    pub premultiplied: bool,
+}
+
+impl Default for TexOptions {
+    fn default() -> TexOptions {
+        TexOptions { filter: TexFilter::Nearest, wrap: TexWrap::Clamp, mipmaps: false, premultiplied: false }
+    }
+}
+pub type RenderTargetId = usize;
+pub type ShaderId = usize;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Uniform {
+    Float(f32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+}
+
+// Fragment shaders output premultiplied color, and vertex colors are expected to be
+// premultiplied. Multiply and Screen assume an opaque destination.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum BlendMode {
+    Normal,
+    Multiply,
+    Screen,
+    Additive,
+    // for sources whose color is already multiplied by alpha, which blend like Normal
+    Premultiplied,
+}
+
 macro_rules! offset {
     ($type:ty, $field:ident) => { &(*(0 as *const $type)).$field as *const _ as usize }
 }
@@ -24,92 +82,285 @@ pub struct VertexUV {
     pub uv: [f32; 2],
 }
 
-const VERT: &[u8] = b"
-#version 330
+// A vertex of a quad covering a rounded rectangle, whose coverage is computed per fragment.
+#[derive(Copy, Clone, Debug)]
+pub struct VertexShape {
+    pub pos: [f32; 3],
+    pub col: [f32; 4],
+    // position relative to the center of the rectangle, in pixels
+    pub uv: [f32; 2],
+    // half width, half height and corner radius of the rectangle, in pixels
+    pub shape: [f32; 3],
+}
 
-layout(location = 0) in vec3 pos;
-layout(location = 1) in vec4 col;
+// The OpenGL variant a renderer targets. WebGL 1 and 2 contexts report themselves as
+// OpenGL ES 2.0 and 3.0 respectively.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum GlApi {
+    // OpenGL 3.3 core, with dual source blending for subpixel text
+    Gl33,
+    Gles3,
+    Gles2,
+}
 
-out vec4 v_col;
+// Shader bodies are shared between APIs and written against the macros defined in the
+// headers below. Attribute locations are bound before linking rather than with layout
+// qualifiers, which OpenGL ES 2 lacks.
+const VERT_HEADER_GL33: &str = "#version 330
+#define ATTRIBUTE in
+#define VARYING out
+";
+const VERT_HEADER_GLES3: &str = "#version 300 es
+#define ATTRIBUTE in
+#define VARYING out
+";
+const VERT_HEADER_GLES2: &str = "#version 100
+#define ATTRIBUTE attribute
+#define VARYING varying
+";
+const FRAG_HEADER_GL33: &str = "#version 330
+#define DUAL_SOURCE
+#define DERIVATIVES
+#define VARYING in
+#define TEXTURE texture
+out vec4 f_col;
+vec4 encode(vec4 col) { return col; }
+";
+// without FRAMEBUFFER_SRGB, colors are converted to sRGB in the fragment shader
+const FRAG_HEADER_GLES3: &str = "#version 300 es
+#define DERIVATIVES
+precision mediump float;
+#define VARYING in
+#define TEXTURE texture
+out vec4 f_col;
+vec4 encode(vec4 col) { return vec4(pow(col.rgb / max(col.a, 1.0 / 255.0), vec3(1.0 / 2.2)) * col.a, col.a); }
+";
+const FRAG_HEADER_GLES2: &str = "#version 100
+#ifdef GL_OES_standard_derivatives
+#extension GL_OES_standard_derivatives : enable
+#define DERIVATIVES
+#endif
+precision mediump float;
+#define VARYING varying
+#define TEXTURE texture2D
+#define f_col gl_FragColor
+vec4 encode(vec4 col) { return vec4(pow(col.rgb / max(col.a, 1.0 / 255.0), vec3(1.0 / 2.2)) * col.a, col.a); }
+";
+
+const VERT: &str = "
+ATTRIBUTE vec3 pos;
+ATTRIBUTE vec4 col;
+
+VARYING vec4 v_col;
 
 void main() {
     gl_Position = vec4(pos, 1.0);
     v_col = col;
 }
-\0";
-const FRAG: &[u8] = b"
-#version 330
+";
+const FRAG: &str = "
+VARYING vec4 v_col;
 
-in vec4 v_col;
+void main() {
+    f_col = encode(v_col);
+}
+";
+const VERT_TEX: &str = "
+ATTRIBUTE vec3 pos;
+ATTRIBUTE vec4 col;
+ATTRIBUTE vec2 uv;
 
-out vec4 f_col;
+VARYING vec4 v_col;
+VARYING vec2 v_uv;
 
 void main() {
-    f_col = v_col;
+    gl_Position = vec4(pos, 1.0);
+    v_uv = uv;
+    v_col = col;
 }
-\0";
-const VERT_TEX_RGBA: &[u8] = b"
-#version 330
-
-layout(location = 0) in vec3 pos;
-layout(location = 1) in vec4 col;
-layout(location = 2) in vec2 uv;
+";
+const VERT_SHAPE: &str = "
+ATTRIBUTE vec3 pos;
+ATTRIBUTE vec4 col;
+ATTRIBUTE vec2 uv;
+ATTRIBUTE vec3 shape;
 
-out vec2 v_uv;
-out vec4 v_col;
+VARYING vec4 v_col;
+VARYING vec2 v_uv;
+VARYING vec3 v_shape;
 
 void main() {
     gl_Position = vec4(pos, 1.0);
-    v_uv = uv;
     v_col = col;
+    v_uv = uv;
+    v_shape = shape;
+}
+";
+const FRAG_SHAPE: &str = "
+VARYING vec4 v_col;
+VARYING vec2 v_uv;
+VARYING vec3 v_shape;
+
+void main() {
+    // shapes thinner than a pixel are widened to one, with their coverage scaled down to match
+    vec2 half_size = max(v_shape.xy, vec2(0.5));
+    float radius = min(v_shape.z, min(half_size.x, half_size.y));
+    vec2 q = abs(v_uv) - half_size + radius;
+    float dist = length(max(q, 0.0)) + min(max(q.x, q.y), 0.0) - radius;
+    float coverage = clamp(0.5 - dist, 0.0, 1.0) * (v_shape.x / half_size.x) * (v_shape.y / half_size.y);
+    f_col = encode(v_col * coverage);
 }
-\0";
-const FRAG_TEX_RGBA: &[u8] = b"
-#version 330
+";
+const FRAG_TEX_RGBA: &str = "
+uniform sampler2D tex;
+
+VARYING vec4 v_col;
+VARYING vec2 v_uv;
 
+void main() {
+    f_col = encode(v_col * TEXTURE(tex, v_uv));
+}
+";
+// for textures holding colors encoded by the shaders rather than by an sRGB format
+const FRAG_TEX_ENCODED: &str = "
 uniform sampler2D tex;
 
-in vec2 v_uv;
-in vec4 v_col;
+VARYING vec4 v_col;
+VARYING vec2 v_uv;
 
-out vec4 f_col;
+void main() {
+    vec4 col = TEXTURE(tex, v_uv);
+    f_col = encode(v_col * vec4(pow(col.rgb / max(col.a, 1.0 / 255.0), vec3(2.2)) * col.a, col.a));
+}
+";
+const FRAG_TEX_A: &str = "
+uniform sampler2D tex;
+uniform float gamma;
+uniform float contrast;
+
+VARYING vec4 v_col;
+VARYING vec2 v_uv;
 
 void main() {
-    f_col = v_col * texture(tex, v_uv).rgba;
+    float luminance = dot(v_col.rgb / max(v_col.a, 1.0 / 255.0), vec3(0.2126, 0.7152, 0.0722));
+    float coverage = pow(TEXTURE(tex, v_uv).r, mix(gamma, 1.0 / gamma, luminance));
+    coverage = mix(coverage, smoothstep(0.0, 1.0, coverage), contrast);
+    f_col = encode(v_col * coverage);
 }
-\0";
-const VERT_TEX_A: &[u8] = b"
-#version 330
+";
+const FRAG_TEX_LCD: &str = "
+uniform sampler2D tex;
+uniform float gamma;
+uniform float contrast;
 
-layout(location = 0) in vec3 pos;
-layout(location = 1) in vec4 col;
-layout(location = 2) in vec2 uv;
+VARYING vec4 v_col;
+VARYING vec2 v_uv;
 
-out vec4 v_col;
-out vec2 v_uv;
+#ifdef DUAL_SOURCE
+out vec4 f_mask;
+#endif
 
 void main() {
-    gl_Position = vec4(pos, 1.0);
-    v_uv = uv;
-    v_col = col;
+    vec3 color = v_col.rgb / max(v_col.a, 1.0 / 255.0);
+    float luminance = dot(color, vec3(0.2126, 0.7152, 0.0722));
+    vec3 coverage = pow(TEXTURE(tex, v_uv).rgb, vec3(mix(gamma, 1.0 / gamma, luminance)));
+    coverage = mix(coverage, smoothstep(0.0, 1.0, coverage), contrast);
+#ifdef DUAL_SOURCE
+    f_col = vec4(color, 1.0);
+    f_mask = vec4(v_col.a * coverage, v_col.a);
+#else
+    // each channel can only be blended with its own coverage through dual source blending
+    f_col = encode(v_col * dot(coverage, vec3(1.0 / 3.0)));
+#endif
 }
-\0";
-const FRAG_TEX_A: &[u8] = b"
-#version 330
-
+";
+const FRAG_TEX_SDF: &str = "
 uniform sampler2D tex;
 
-in vec4 v_col;
-in vec2 v_uv;
-
-out vec4 f_col;
+VARYING vec4 v_col;
+VARYING vec2 v_uv;
 
 void main() {
-    f_col = v_col * vec4(1, 1, 1, texture(tex, v_uv).r);
+    float dist = TEXTURE(tex, v_uv).r;
+#ifdef DERIVATIVES
+    float width = 0.7 * fwidth(dist);
+#else
+    float width = 0.05;
+#endif
+    f_col = encode(v_col * smoothstep(0.5 - width, 0.5 + width, dist));
+}
+";
+
+// Unsized single channel format of OpenGL ES 2, missing from the core profile bindings.
+const LUMINANCE: GLenum = 0x1909;
+
+#[derive(Debug)]
+pub enum RendererError {
+    // the context's GL version string
+    UnsupportedVersion(String),
+    ShaderCompile(String),
+    ProgramLink(String),
+    InvalidTexture,
+    // pixel data that does not match the size given, or a region outside the texture
+    TextureSize,
+    InvalidRenderTarget,
+    // a region that does not fit in the bound framebuffer
+    OutOfBounds,
+    // the status returned by CheckFramebufferStatus
+    IncompleteFramebuffer(GLenum),
+    // an operation the context's API lacks
+    Unsupported(&'static str),
+    Gl(GLenum),
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RendererError::UnsupportedVersion(version) => write!(f, "unsupported OpenGL version: {}", version),
+            RendererError::ShaderCompile(log) => write!(f, "shader compilation failed: {}", log),
+            RendererError::ProgramLink(log) => write!(f, "program linking failed: {}", log),
+            RendererError::InvalidTexture => write!(f, "invalid texture id"),
+            RendererError::TextureSize => write!(f, "texture data does not match texture size"),
+            RendererError::InvalidRenderTarget => write!(f, "invalid render target id"),
+            RendererError::OutOfBounds => write!(f, "region outside the framebuffer"),
+            RendererError::IncompleteFramebuffer(status) => write!(f, "incomplete framebuffer: 0x{:x}", status),
+            RendererError::Unsupported(operation) => write!(f, "{} is not supported by this OpenGL version", operation),
+            RendererError::Gl(error) => write!(f, "OpenGL error 0x{:x}", error),
+        }
+    }
+}
+
+impl std::error::Error for RendererError {}
+
+// Parses a GL_VERSION string into its major and minor version and whether it is OpenGL ES.
+fn parse_version(version: &str) -> Option<(u32, u32, bool)> {
+    // some WebGL implementations report their own version
+    if version.starts_with("WebGL ") {
+        let (major, _, _) = parse_version(&version[6..])?;
+        return Some((major + 1, 0, true));
+    }
+    let (es, version) = if version.starts_with("OpenGL ES") {
+        // OpenGL ES versions may be followed by a profile, as in "OpenGL ES-CM 1.1"
+        (true, version.splitn(2, ' ').nth(1)?.splitn(2, ' ').nth(1)?)
+    } else {
+        (false, version)
+    };
+    let mut numbers = version.split(|c: char| !c.is_ascii_digit());
+    let major = numbers.next()?.parse().ok()?;
+    let minor = numbers.next()?.parse().ok()?;
+    Some((major, minor, es))
 }
-\0";
 
-fn shader(shader_src: &CStr, shader_type: GLenum) -> Result<GLuint, String> {
+fn gl_version() -> String {
+    unsafe {
+        let version = gl::GetString(gl::VERSION);
+        if version.is_null() { return String::new(); }
+        CStr::from_ptr(version as *const std::os::raw::c_char).to_string_lossy().into_owned()
+    }
+}
+
+fn shader(header: &str, body: &str, shader_type: GLenum) -> Result<GLuint, RendererError> {
+    let shader_src = CString::new(format!("{}{}", header, body)).unwrap();
     unsafe {
         let shader: GLuint = gl::CreateShader(shader_type);
         gl::ShaderSource(shader, 1, &shader_src.as_ptr(), std::ptr::null());
@@ -120,220 +371,880 @@ fn shader(shader_src: &CStr, shader_type: GLenum) -> Result<GLuint, String> {
         if valid == 0 {
             let mut len: GLint = 0;
             gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut len);
-            let error = CString::new(vec![' ' as u8; len as usize]).unwrap();
-            gl::GetShaderInfoLog(shader, len, std::ptr::null_mut(), error.as_ptr() as *mut GLchar);
-            return Err(error.into_string().unwrap());
+            let mut log = vec![0; len.max(1) as usize];
+            let mut written: GLsizei = 0;
+            gl::GetShaderInfoLog(shader, log.len() as GLsizei, &mut written, log.as_mut_ptr() as *mut GLchar);
+            log.truncate(written as usize);
+            gl::DeleteShader(shader);
+            return Err(RendererError::ShaderCompile(String::from_utf8_lossy(&log).into_owned()));
         }
 
         Ok(shader)
     }
 }
 
-fn program(vert_src: &CStr, frag_src: &CStr) -> Result<GLuint, String> {
+fn program(api: GlApi, vert_src: &str, frag_src: &str) -> Result<GLuint, RendererError> {
+    let (vert_header, frag_header) = match api {
+        GlApi::Gl33 => (VERT_HEADER_GL33, FRAG_HEADER_GL33),
+        GlApi::Gles3 => (VERT_HEADER_GLES3, FRAG_HEADER_GLES3),
+        GlApi::Gles2 => (VERT_HEADER_GLES2, FRAG_HEADER_GLES2),
+    };
     unsafe {
-        let vert = shader(vert_src, gl::VERTEX_SHADER).unwrap();
-        let frag = shader(frag_src, gl::FRAGMENT_SHADER).unwrap();
+        let vert = shader(vert_header, vert_src, gl::VERTEX_SHADER)?;
+        let frag = match shader(frag_header, frag_src, gl::FRAGMENT_SHADER) {
+            Ok(frag) => frag,
+            Err(error) => {
+                gl::DeleteShader(vert);
+                return Err(error);
+            }
+        };
         let prog = gl::CreateProgram();
         gl::AttachShader(prog, vert);
         gl::AttachShader(prog, frag);
+        for (i, name) in [&b"pos\0"[..], b"col\0", b"uv\0", b"shape\0"].iter().enumerate() {
+            gl::BindAttribLocation(prog, i as GLuint, name.as_ptr() as *const GLchar);
+        }
+        if api == GlApi::Gl33 {
+            gl::BindFragDataLocationIndexed(prog, 0, 0, b"f_col\0".as_ptr() as *const GLchar);
+            gl::BindFragDataLocationIndexed(prog, 0, 1, b"f_mask\0".as_ptr() as *const GLchar);
+        }
         gl::LinkProgram(prog);
 
+        gl::DetachShader(prog, vert);
+        gl::DetachShader(prog, frag);
+
+        gl::DeleteShader(vert);
+        gl::DeleteShader(frag);
+
         let mut valid: GLint = 1;
-        gl::GetProgramiv(prog, gl::COMPILE_STATUS, &mut valid);
+        gl::GetProgramiv(prog, gl::LINK_STATUS, &mut valid);
         if valid == 0 {
             let mut len: GLint = 0;
             gl::GetProgramiv(prog, gl::INFO_LOG_LENGTH, &mut len);
-            let error = CString::new(vec![' ' as u8; len as usize]).unwrap();
-            gl::GetProgramInfoLog(prog, len, std::ptr::null_mut(), error.as_ptr() as *mut GLchar);
-            return Err(error.into_string().unwrap());
+            let mut log = vec![0; len.max(1) as usize];
+            let mut written: GLsizei = 0;
+            gl::GetProgramInfoLog(prog, log.len() as GLsizei, &mut written, log.as_mut_ptr() as *mut GLchar);
+            log.truncate(written as usize);
+            gl::DeleteProgram(prog);
+            return Err(RendererError::ProgramLink(String::from_utf8_lossy(&log).into_owned()));
         }
 
-        gl::DetachShader(prog, vert);
-        gl::DetachShader(prog, frag);
+        Ok(prog)
+    }
+}
 
-        gl::DeleteShader(vert);
-        gl::DeleteShader(frag);
+fn uniform(prog: GLuint, name: &[u8]) -> GLint {
+    unsafe { gl::GetUniformLocation(prog, CStr::from_bytes_with_nul(name).unwrap().as_ptr()) }
+}
 
-        Ok(prog)
+// A vertex array object with its own vertex and index buffers, kept for the lifetime of the
+// renderer. The buffers are refilled on every draw and grown when they are too small.
+struct Buffers {
+    // OpenGL ES 2 has no vertex array objects, so attributes are set up on every draw
+    vao: Option<GLuint>,
+    vbo: GLuint,
+    ibo: GLuint,
+    vbo_capacity: usize,
+    ibo_capacity: usize,
+    stride: usize,
+    attribs: Vec<(GLint, usize)>,
+}
+
+impl Buffers {
+    // `attribs` lists the (component count, byte offset) of each float attribute in order.
+    fn new(api: GlApi, stride: usize, attribs: &[(GLint, usize)]) -> Buffers {
+        let mut buffers = Buffers { vao: None, vbo: 0, ibo: 0, vbo_capacity: 0, ibo_capacity: 0, stride, attribs: attribs.to_vec() };
+        unsafe {
+            gl::GenBuffers(1, &mut buffers.vbo);
+            gl::GenBuffers(1, &mut buffers.ibo);
+
+            if api != GlApi::Gles2 {
+                let mut vao = 0;
+                gl::GenVertexArrays(1, &mut vao);
+                gl::BindVertexArray(vao);
+                // the element buffer binding is part of the vertex array state
+                buffers.bind_attribs();
+                buffers.vao = Some(vao);
+            }
+        }
+        buffers
+    }
+
+    unsafe fn bind_attribs(&self) {
+        gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ibo);
+        for (i, &(size, offset)) in self.attribs.iter().enumerate() {
+            gl::EnableVertexAttribArray(i as GLuint);
+            gl::VertexAttribPointer(i as GLuint, size, gl::FLOAT, gl::FALSE, self.stride as GLint, offset as *const gl::types::GLvoid);
+        }
+    }
+
+    // Binds the buffers with their attributes and copies the vertices and indices into them.
+    fn upload<V>(&mut self, vertices: &[V], indices: &[u16]) {
+        let vertex_bytes = vertices.len() * std::mem::size_of::<V>();
+        let index_bytes = indices.len() * std::mem::size_of::<u16>();
+        unsafe {
+            if let Some(vao) = self.vao {
+                gl::BindVertexArray(vao);
+                gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            } else {
+                self.bind_attribs();
+                // attributes left enabled by a draw with more of them would read past the buffer
+                for i in self.attribs.len()..4 {
+                    gl::DisableVertexAttribArray(i as GLuint);
+                }
+            }
+            self.vbo_capacity = fill_buffer(gl::ARRAY_BUFFER, self.vbo_capacity, vertex_bytes, vertices.as_ptr() as *const std::ffi::c_void);
+            self.ibo_capacity = fill_buffer(gl::ELEMENT_ARRAY_BUFFER, self.ibo_capacity, index_bytes, indices.as_ptr() as *const std::ffi::c_void);
+        }
     }
 }
 
+// Zero handles were never created, as in a headless renderer, and are skipped.
+impl Drop for Buffers {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(vao) = self.vao {
+                gl::DeleteVertexArrays(1, &vao);
+            }
+            if self.ibo != 0 {
+                gl::DeleteBuffers(1, &self.ibo);
+            }
+            if self.vbo != 0 {
+                gl::DeleteBuffers(1, &self.vbo);
+            }
+        }
+    }
+}
+
+// Copies `size` bytes into the buffer bound to `target`, reallocating it at double its
+// previous capacity if it is too small. Returns the new capacity.
+unsafe fn fill_buffer(target: GLenum, capacity: usize, size: usize, data: *const std::ffi::c_void) -> usize {
+    let mut capacity = capacity;
+    if size > capacity {
+        capacity = size.max(2 * capacity);
+        gl::BufferData(target, capacity as isize, std::ptr::null(), gl::DYNAMIC_DRAW);
+    }
+    gl::BufferSubData(target, 0, size as isize, data);
+    capacity
+}
+
 struct Texture {
     format: TexFormat,
+    options: TexOptions,
+    // whether the texels were encoded by the shaders, as render targets are without sRGB
+    // formats, and need decoding when sampled
+    encoded: bool,
     tex: GLuint,
+    width: usize,
+    height: usize,
+}
+
+// A framebuffer with a color texture attached, which can be drawn into and then drawn with
+// draw_tex like any other texture.
+struct RenderTarget {
+    fbo: GLuint,
+    // renderbuffer for the stencil buffer used by fill_stencil
+    stencil: GLuint,
+    // the color renderbuffer of a multisampled target, and 0 otherwise
+    color: GLuint,
+    tex: Option<TexId>,
+    width: usize,
+    height: usize,
 }
 
 pub struct Renderer {
+    api: GlApi,
+
     prog: GLuint,
     prog_tex_rgba: GLuint,
+    prog_tex_encoded: GLuint,
     prog_tex_a: GLuint,
+    prog_tex_lcd: GLuint,
+    prog_tex_sdf: GLuint,
+    prog_shape: GLuint,
+
+    buffers: Buffers,
+    buffers_uv: Buffers,
+    buffers_shape: Buffers,
 
     textures: Slab<Texture>,
+    render_targets: Slab<RenderTarget>,
+    shaders: Slab<GLuint>,
+    // the viewport of the default framebuffer, restored when it is bound again
+    default_viewport: [GLint; 4],
+    target: Option<RenderTargetId>,
+    blend: BlendMode,
+
+    text_gamma: f32,
+    text_contrast: f32,
+
+    // since the last reset_counts
+    draw_calls: usize,
+    vertices: usize,
+    submit_time: Duration,
+
+    // two queries used in alternate frames, so that reading one doesn't wait on the other
+    #[cfg(feature = "gpu-timer")]
+    timer_queries: [GLuint; 2],
+    #[cfg(feature = "gpu-timer")]
+    timer_frame: usize,
 }
 
 impl Renderer {
-    pub fn new() -> Renderer {
-        let prog: GLuint = program(
-            &CStr::from_bytes_with_nul(VERT).unwrap(),
-            &CStr::from_bytes_with_nul(FRAG).unwrap()).unwrap();
-
-        let prog_tex_rgba: GLuint = program(
-            &CStr::from_bytes_with_nul(VERT_TEX_RGBA).unwrap(),
-            &CStr::from_bytes_with_nul(FRAG_TEX_RGBA).unwrap()).unwrap();
-
-        let prog_tex_a: GLuint = program(
-            &CStr::from_bytes_with_nul(VERT_TEX_A).unwrap(),
-            &CStr::from_bytes_with_nul(FRAG_TEX_A).unwrap()).unwrap();
-
+    // Picks shaders to match the current context. Fails if the context is older than
+    // OpenGL 3.3 or OpenGL ES 2.0, or the shaders cannot be built, so that the caller can
+    // fall back to another renderer.
+    pub fn new() -> Result<Renderer, RendererError> {
+        let version = gl_version();
+        let api = match parse_version(&version) {
+            Some((major, minor, false)) if (major, minor) >= (3, 3) => GlApi::Gl33,
+            Some((major, _, true)) if major >= 3 => GlApi::Gles3,
+            Some((2, _, true)) => GlApi::Gles2,
+            _ => return Err(RendererError::UnsupportedVersion(version)),
+        };
+
+        let buffers = unsafe { Buffers::new(api, std::mem::size_of::<Vertex>(), &[
+            (3, offset!(Vertex, pos)),
+            (4, offset!(Vertex, col)),
+        ]) };
+        let buffers_uv = unsafe { Buffers::new(api, std::mem::size_of::<VertexUV>(), &[
+            (3, offset!(VertexUV, pos)),
+            (4, offset!(VertexUV, col)),
+            (2, offset!(VertexUV, uv)),
+        ]) };
+        let buffers_shape = unsafe { Buffers::new(api, std::mem::size_of::<VertexShape>(), &[
+            (3, offset!(VertexShape, pos)),
+            (4, offset!(VertexShape, col)),
+            (2, offset!(VertexShape, uv)),
+            (3, offset!(VertexShape, shape)),
+        ]) };
+
+        // programs are filled in one at a time, so that dropping the renderer on an error
+        // deletes the ones already built
+        let mut renderer = Renderer::with_buffers(api, buffers, buffers_uv, buffers_shape);
+        renderer.prog = program(api, VERT, FRAG)?;
+        renderer.prog_tex_rgba = program(api, VERT_TEX, FRAG_TEX_RGBA)?;
+        renderer.prog_tex_encoded = program(api, VERT_TEX, FRAG_TEX_ENCODED)?;
+        renderer.prog_tex_a = program(api, VERT_TEX, FRAG_TEX_A)?;
+        renderer.prog_tex_lcd = program(api, VERT_TEX, FRAG_TEX_LCD)?;
+        // distance fields can be evaluated on the CPU instead, see supports_distance_field
+        renderer.prog_tex_sdf = program(api, VERT_TEX, FRAG_TEX_SDF).unwrap_or(0);
+        renderer.prog_shape = program(api, VERT_SHAPE, FRAG_SHAPE)?;
+
+        renderer.blend_mode(BlendMode::Normal);
         unsafe {
-            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
             gl::Enable(gl::BLEND);
-            gl::Enable(gl::FRAMEBUFFER_SRGB);
+            if api == GlApi::Gl33 {
+                gl::Enable(gl::FRAMEBUFFER_SRGB);
+            }
         }
 
+        Ok(renderer)
+    }
+
+    fn with_buffers(api: GlApi, buffers: Buffers, buffers_uv: Buffers, buffers_shape: Buffers) -> Renderer {
         Renderer {
-            prog,
-            prog_tex_rgba,
-            prog_tex_a,
+            api,
+
+            prog: 0,
+            prog_tex_rgba: 0,
+            prog_tex_encoded: 0,
+            prog_tex_a: 0,
+            prog_tex_lcd: 0,
+            prog_tex_sdf: 0,
+            prog_shape: 0,
+
+            buffers,
+            buffers_uv,
+            buffers_shape,
 
             textures: Slab::new(),
+            render_targets: Slab::new(),
+            shaders: Slab::new(),
+            default_viewport: [0; 4],
+            target: None,
+            blend: BlendMode::Normal,
+
+            text_gamma: 1.0,
+            text_contrast: 0.0,
+
+            draw_calls: 0,
+            vertices: 0,
+            submit_time: Duration::default(),
+
+            #[cfg(feature = "gpu-timer")]
+            timer_queries: [0; 2],
+            #[cfg(feature = "gpu-timer")]
+            timer_frame: 0,
+        }
+    }
+
+    // A renderer without an OpenGL context, for testing what is built on top of it without
+    // drawing. It holds no OpenGL objects, so dropping it makes no OpenGL calls.
+    #[cfg(test)]
+    pub fn headless() -> Renderer {
+        let buffers = || Buffers { vao: None, vbo: 0, ibo: 0, vbo_capacity: 0, ibo_capacity: 0, stride: 0, attribs: Vec::new() };
+        Renderer::with_buffers(GlApi::Gl33, buffers(), buffers(), buffers())
+    }
+
+    pub fn api(&self) -> GlApi {
+        self.api
+    }
+
+    // Subpixel text needs dual source blending. Without it, subpixel glyphs are drawn with
+    // their channels averaged into grayscale coverage.
+    pub fn supports_subpixel_text(&self) -> bool {
+        self.api == GlApi::Gl33
+    }
+
+    // Whether SDF textures can be drawn, which fails on drivers that can't build their
+    // shader. Without it, distance field glyphs are turned into coverage on the CPU.
+    pub fn supports_distance_field(&self) -> bool {
+        self.prog_tex_sdf != 0
+    }
+
+    // Coverage of light text on a dark background is raised to 1/gamma and that of dark
+    // text on a light background to gamma. Contrast pushes coverage towards 0 or 1.
+    pub fn text_gamma(&mut self, gamma: f32, contrast: f32) {
+        self.text_gamma = gamma;
+        self.text_contrast = contrast;
+    }
+
+    // Subpixel text is always blended source over, as its dual source blending leaves no
+    // room for another blend function.
+    pub fn blend_mode(&mut self, mode: BlendMode) {
+        self.blend = mode;
+        let (src, dst) = match mode {
+            BlendMode::Normal | BlendMode::Premultiplied => (gl::ONE, gl::ONE_MINUS_SRC_ALPHA),
+            BlendMode::Multiply => (gl::DST_COLOR, gl::ONE_MINUS_SRC_ALPHA),
+            BlendMode::Screen => (gl::ONE, gl::ONE_MINUS_SRC_COLOR),
+            BlendMode::Additive => (gl::ONE, gl::ONE),
+        };
+        unsafe {
+            gl::BlendFuncSeparate(src, dst, gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+        }
+    }
+
+    fn count(&mut self, vertices: usize) {
+        self.draw_calls += 1;
+        self.vertices += vertices;
+    }
+
+    // Draw calls made and vertices uploaded since the last reset_counts. A stencil fill
+    // counts as two draw calls.
+    pub fn draw_calls(&self) -> usize {
+        self.draw_calls
+    }
+
+    pub fn vertices(&self) -> usize {
+        self.vertices
+    }
+
+    // CPU time spent in clears, draws and blits since the last reset_counts, including
+    // uploading their vertices.
+    pub fn submit_time(&self) -> Duration {
+        self.submit_time
+    }
+
+    pub fn reset_counts(&mut self) {
+        self.draw_calls = 0;
+        self.vertices = 0;
+        self.submit_time = Duration::default();
+    }
+
+    // Measures the time the GPU spends on the commands issued until end_gpu_timer. Needs
+    // timer queries, which are only used on OpenGL 3.3.
+    #[cfg(feature = "gpu-timer")]
+    pub fn begin_gpu_timer(&mut self) {
+        if self.api != GlApi::Gl33 { return; }
+        unsafe {
+            if self.timer_queries == [0; 2] {
+                gl::GenQueries(2, self.timer_queries.as_mut_ptr());
+            }
+            gl::BeginQuery(gl::TIME_ELAPSED, self.timer_queries[self.timer_frame % 2]);
+        }
+    }
+
+    // Returns the time measured for the previous frame rather than this one, which the GPU
+    // has usually not finished yet, or None if that isn't available without waiting.
+    #[cfg(feature = "gpu-timer")]
+    pub fn end_gpu_timer(&mut self) -> Option<Duration> {
+        if self.api != GlApi::Gl33 { return None; }
+        unsafe {
+            gl::EndQuery(gl::TIME_ELAPSED);
+            self.timer_frame += 1;
+            if self.timer_frame < 2 { return None; }
+            let query = self.timer_queries[self.timer_frame % 2];
+            let mut available: GLint = 0;
+            gl::GetQueryObjectiv(query, gl::QUERY_RESULT_AVAILABLE, &mut available);
+            if available == 0 { return None; }
+            let mut nanos: u64 = 0;
+            gl::GetQueryObjectui64v(query, gl::QUERY_RESULT, &mut nanos);
+            Some(Duration::from_nanos(nanos))
         }
     }
 
     pub fn clear(&mut self, col: [f32; 4]) {
+        // matches the encoding done by the fragment shaders without FRAMEBUFFER_SRGB
+        let col = if self.api == GlApi::Gl33 { col } else {
+            [col[0].powf(1.0 / 2.2), col[1].powf(1.0 / 2.2), col[2].powf(1.0 / 2.2), col[3]]
+        };
+        let start = Instant::now();
         unsafe {
             gl::ClearColor(col[0], col[1], col[2], col[3]);
-            gl::Clear(gl::COLOR_BUFFER_BIT);
+            gl::ClearStencil(0);
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
         }
+        self.submit_time += start.elapsed();
     }
 
     pub fn draw(&mut self, vertices: &[Vertex], indices: &[u16]) {
+        if indices.is_empty() { return; }
+        let start = Instant::now();
+        self.count(vertices.len());
+        self.buffers.upload(vertices, indices);
         unsafe {
-            let mut vbo: u32 = 0;
-            gl::GenBuffers(1, &mut vbo);
-            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-            gl::BufferData(gl::ARRAY_BUFFER, (vertices.len() * std::mem::size_of::<Vertex>()) as isize, vertices.as_ptr() as *const std::ffi::c_void, gl::STATIC_DRAW);
-
-            let mut ibo: u32 = 0;
-            gl::GenBuffers(1, &mut ibo);
-            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ibo);
-            gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, (indices.len() * std::mem::size_of::<u16>()) as isize, indices.as_ptr() as *const std::ffi::c_void, gl::STATIC_DRAW);
-
-            let mut vao: u32 = 0;
-            gl::GenVertexArrays(1, &mut vao);
-            gl::BindVertexArray(vao);
-            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ibo);
-
-            gl::EnableVertexAttribArray(0);
-            gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, std::mem::size_of::<Vertex>() as GLint, offset!(Vertex, pos) as *const gl::types::GLvoid);
-            gl::EnableVertexAttribArray(1);
-            gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, std::mem::size_of::<Vertex>() as GLint, offset!(Vertex, col) as *const gl::types::GLvoid);
-
             gl::UseProgram(self.prog);
 
             gl::DrawElements(gl::TRIANGLES, indices.len() as i32, gl::UNSIGNED_SHORT, 0 as *const gl::types::GLvoid);
+        }
+        self.submit_time += start.elapsed();
+    }
 
-            gl::DisableVertexAttribArray(0);
-            gl::DisableVertexAttribArray(1);
+    pub fn draw_shapes(&mut self, vertices: &[VertexShape], indices: &[u16]) {
+        if indices.is_empty() { return; }
+        let start = Instant::now();
+        self.count(vertices.len());
+        self.buffers_shape.upload(vertices, indices);
+        unsafe {
+            gl::UseProgram(self.prog_shape);
 
-            gl::DeleteVertexArrays(1, &vao);
-            gl::DeleteBuffers(1, &ibo);
-            gl::DeleteBuffers(1, &vbo);
+            gl::DrawElements(gl::TRIANGLES, indices.len() as i32, gl::UNSIGNED_SHORT, 0 as *const gl::types::GLvoid);
+        }
+        self.submit_time += start.elapsed();
+    }
+
+    // Fills a polygon of any shape with the nonzero rule. Its triangles count the winding
+    // number of each pixel into the stencil buffer, the fringe geometry is drawn wherever
+    // that is zero to antialias the edges, and the cover geometry is then drawn wherever it
+    // is nonzero, resetting the stencil buffer as it goes. Needs a stencil buffer.
+    pub fn fill_stencil(&mut self, vertices: &[Vertex], indices: &[u16], fringe: &[Vertex], fringe_indices: &[u16], cover: &[Vertex], cover_indices: &[u16]) {
+        if indices.is_empty() || cover_indices.is_empty() { return; }
+        unsafe {
+            gl::Enable(gl::STENCIL_TEST);
+            gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
+            gl::StencilFunc(gl::ALWAYS, 0, 0xff);
+            gl::StencilOpSeparate(gl::FRONT, gl::KEEP, gl::KEEP, gl::INCR_WRAP);
+            gl::StencilOpSeparate(gl::BACK, gl::KEEP, gl::KEEP, gl::DECR_WRAP);
+        }
+        self.draw(vertices, indices);
+        unsafe {
+            gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+            gl::StencilFunc(gl::EQUAL, 0, 0xff);
+            gl::StencilOp(gl::KEEP, gl::KEEP, gl::KEEP);
+        }
+        self.draw(fringe, fringe_indices);
+        unsafe {
+            gl::StencilFunc(gl::NOTEQUAL, 0, 0xff);
+            gl::StencilOp(gl::ZERO, gl::ZERO, gl::ZERO);
+        }
+        self.draw(cover, cover_indices);
+        unsafe {
+            gl::Disable(gl::STENCIL_TEST);
         }
     }
 
     pub fn draw_tex(&mut self, vertices: &[VertexUV], indices: &[u16], tex_id: TexId) {
+        if indices.is_empty() { return; }
+        let start = Instant::now();
+        self.count(vertices.len());
+        self.buffers_uv.upload(vertices, indices);
         let tex = self.textures.get(tex_id).unwrap();
         unsafe {
-            let mut vbo: u32 = 0;
-            gl::GenBuffers(1, &mut vbo);
-            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-            gl::BufferData(gl::ARRAY_BUFFER, (vertices.len() * std::mem::size_of::<VertexUV>()) as isize, vertices.as_ptr() as *const std::ffi::c_void, gl::STATIC_DRAW);
-
-            let mut ibo: u32 = 0;
-            gl::GenBuffers(1, &mut ibo);
-            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ibo);
-            gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, (indices.len() * std::mem::size_of::<u16>()) as isize, indices.as_ptr() as *const std::ffi::c_void, gl::STATIC_DRAW);
-
-            let mut vao: u32 = 0;
-            gl::GenVertexArrays(1, &mut vao);
-            gl::BindVertexArray(vao);
-            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ibo);
-
-            gl::EnableVertexAttribArray(0);
-            gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, std::mem::size_of::<VertexUV>() as GLint, offset!(VertexUV, pos) as *const gl::types::GLvoid);
-            gl::EnableVertexAttribArray(1);
-            gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, std::mem::size_of::<VertexUV>() as GLint, offset!(VertexUV, col) as *const gl::types::GLvoid);
-            gl::EnableVertexAttribArray(2);
-            gl::VertexAttribPointer(2, 2, gl::FLOAT, gl::FALSE, std::mem::size_of::<VertexUV>() as GLint, offset!(VertexUV, uv) as *const gl::types::GLvoid);
-
             gl::ActiveTexture(gl::TEXTURE0);
             gl::BindTexture(gl::TEXTURE_2D, tex.tex);
 
+            let prog = match tex.format {
+                TexFormat::RGBA if tex.encoded => self.prog_tex_encoded,
+                TexFormat::RGBA | TexFormat::SRGBA => self.prog_tex_rgba,
+                TexFormat::A => self.prog_tex_a,
+                TexFormat::LCD => self.prog_tex_lcd,
+                TexFormat::SDF => self.prog_tex_sdf,
+            };
+            gl::UseProgram(prog);
+            gl::Uniform1i(uniform(prog, b"tex\0"), 0);
             match tex.format {
-                TexFormat::RGBA => { gl::UseProgram(self.prog_tex_rgba); }
-                TexFormat::A => { gl::UseProgram(self.prog_tex_a); }
+                TexFormat::A | TexFormat::LCD => {
+                    gl::Uniform1f(uniform(prog, b"gamma\0"), self.text_gamma);
+                    gl::Uniform1f(uniform(prog, b"contrast\0"), self.text_contrast);
+                }
+                _ => {}
+            }
+
+            // subpixel text blends each color channel with its own coverage
+            let dual_source = match tex.format {
+                TexFormat::LCD => self.api == GlApi::Gl33,
+                _ => false,
+            };
+            if dual_source {
+                gl::BlendFunc(gl::SRC1_COLOR, gl::ONE_MINUS_SRC1_COLOR);
             }
-            gl::Uniform1i(0, 0);
 
             gl::DrawElements(gl::TRIANGLES, indices.len() as i32, gl::UNSIGNED_SHORT, 0 as *const gl::types::GLvoid);
 
-            gl::DisableVertexAttribArray(0);
-            gl::DisableVertexAttribArray(1);
-            gl::DisableVertexAttribArray(2);
+            if dual_source {
+                self.blend_mode(self.blend);
+            }
+        }
+        self.submit_time += start.elapsed();
+    }
+
+    // Compiles a fragment shader for draw_custom. The source is appended to the same header
+    // as the built in shaders, so it can use VARYING, TEXTURE and encode(), and must write
+    // premultiplied color to f_col. The vertex color and uv are in v_col and v_uv.
+    pub fn create_shader(&mut self, source: &str) -> Result<ShaderId, RendererError> {
+        let prog = program(self.api, VERT_TEX, source)?;
+        Ok(self.shaders.insert(prog))
+    }
 
-            gl::DeleteVertexArrays(1, &vao);
-            gl::DeleteBuffers(1, &ibo);
-            gl::DeleteBuffers(1, &vbo);
+    pub fn draw_custom(&mut self, shader: ShaderId, vertices: &[VertexUV], indices: &[u16], uniforms: &[(&str, Uniform)]) {
+        if indices.is_empty() { return; }
+        let start = Instant::now();
+        self.count(vertices.len());
+        self.buffers_uv.upload(vertices, indices);
+        let prog = *self.shaders.get(shader).unwrap();
+        unsafe {
+            gl::UseProgram(prog);
+            for (name, value) in uniforms.iter() {
+                let name = CString::new(*name).unwrap();
+                let location = gl::GetUniformLocation(prog, name.as_ptr());
+                match *value {
+                    Uniform::Float(x) => gl::Uniform1f(location, x),
+                    Uniform::Vec2(v) => gl::Uniform2f(location, v[0], v[1]),
+                    Uniform::Vec3(v) => gl::Uniform3f(location, v[0], v[1], v[2]),
+                    Uniform::Vec4(v) => gl::Uniform4f(location, v[0], v[1], v[2], v[3]),
+                }
+            }
+
+            gl::DrawElements(gl::TRIANGLES, indices.len() as i32, gl::UNSIGNED_SHORT, 0 as *const gl::types::GLvoid);
+        }
+        self.submit_time += start.elapsed();
+    }
+
+    pub fn delete_shader(&mut self, shader: ShaderId) {
+        let prog = self.shaders.remove(shader).unwrap();
+        unsafe {
+            gl::DeleteProgram(prog);
         }
     }
 
-    pub fn create_tex(&mut self, format: TexFormat, width: usize, height: usize, pixels: &[u8]) -> TexId {
-        let flipped = flip(pixels, width);
+    pub fn create_tex(&mut self, format: TexFormat, options: TexOptions, width: usize, height: usize, pixels: &[u8]) -> Result<TexId, RendererError> {
+        if pixels.len() != width * height * format.bytes_per_pixel() {
+            return Err(RendererError::TextureSize);
+        }
+        let mut max_size: GLint = 0;
+        unsafe { gl::GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut max_size); }
+        if width > max_size as usize || height > max_size as usize {
+            return Err(RendererError::TextureSize);
+        }
+
+        // OpenGL ES 2 can neither repeat nor mipmap textures whose sides aren't powers of two
+        let mut options = options;
+        if self.api == GlApi::Gles2 && !(width.is_power_of_two() && height.is_power_of_two()) {
+            options.wrap = TexWrap::Clamp;
+            options.mipmaps = false;
+        }
+
+        let data = self.tex_data(format, options, width, pixels);
         let mut tex: GLuint = 0;
         unsafe {
+            // discard errors left over from earlier calls
+            while gl::GetError() != gl::NO_ERROR {}
+
             gl::GenTextures(1, &mut tex);
             gl::BindTexture(gl::TEXTURE_2D, tex);
-            match format {
-                TexFormat::RGBA => {
-                    assert!(flipped.len() == width * height * 4);
-                    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
-                    gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA32UI as GLint, width as i32, height as i32, 0, gl::RGBA, gl::UNSIGNED_INT_8_8_8_8, flipped.as_ptr() as *const std::ffi::c_void);
-                }
-                TexFormat::A => {
-                    assert!(flipped.len() == width * height);
-                    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
-                    gl::TexImage2D(gl::TEXTURE_2D, 0, gl::R8 as GLint, width as i32, height as i32, 0, gl::RED, gl::UNSIGNED_BYTE, flipped.as_ptr() as *const std::ffi::c_void);
-                }
+            let (internal_format, pixel_format, alignment) = tex_format(self.api, format);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, alignment);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, internal_format as GLint, width as i32, height as i32, 0, pixel_format, gl::UNSIGNED_BYTE, data.as_ptr() as *const std::ffi::c_void);
+
+            let (min_filter, mag_filter) = match (options.filter, options.mipmaps) {
+                (TexFilter::Nearest, false) => (gl::NEAREST, gl::NEAREST),
+                (TexFilter::Linear, false) => (gl::LINEAR, gl::LINEAR),
+                (TexFilter::Nearest, true) => (gl::NEAREST_MIPMAP_NEAREST, gl::NEAREST),
+                (TexFilter::Linear, true) => (gl::LINEAR_MIPMAP_LINEAR, gl::LINEAR),
+            };
+            let wrap = match options.wrap {
+                TexWrap::Clamp => gl::CLAMP_TO_EDGE,
+                TexWrap::Repeat => gl::REPEAT,
+                TexWrap::MirroredRepeat => gl::MIRRORED_REPEAT,
+            };
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, mag_filter as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, wrap as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, wrap as i32);
+            if options.mipmaps {
+                gl::GenerateMipmap(gl::TEXTURE_2D);
+            }
+
+            // allocation fails with GL_OUT_OF_MEMORY rather than a panic
+            let error = gl::GetError();
+            if error != gl::NO_ERROR {
+                gl::DeleteTextures(1, &tex);
+                return Err(RendererError::Gl(error));
+            }
+        }
+        Ok(self.textures.insert(Texture { format, options, encoded: false, tex, width, height }))
+    }
+
+    pub fn update_tex(&mut self, texture: TexId, x: usize, y: usize, width: usize, height: usize, pixels: &[u8]) -> Result<(), RendererError> {
+        let &Texture { format, options, tex, width: tex_width, height: tex_height, .. } = self.textures.get(texture).ok_or(RendererError::InvalidTexture)?;
+        if pixels.len() != width * height * format.bytes_per_pixel() || x + width > tex_width || y + height > tex_height {
+            return Err(RendererError::TextureSize);
+        }
+        let data = self.tex_data(format, options, width, pixels);
+        let (_, pixel_format, alignment) = tex_format(self.api, format);
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, tex);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, alignment);
+            gl::TexSubImage2D(gl::TEXTURE_2D, 0, x as i32, y as i32, width as i32, height as i32, pixel_format, gl::UNSIGNED_BYTE, data.as_ptr() as *const std::ffi::c_void);
+            if options.mipmaps {
+                gl::GenerateMipmap(gl::TEXTURE_2D);
             }
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
         }
-        self.textures.insert(Texture { format, tex })
+        Ok(())
     }
 
-    pub fn update_tex(&mut self, texture: TexId, x: usize, y: usize, width: usize, height: usize, pixels: &[u8]) {
-        let flipped = flip(pixels, width);
-        let Texture { format, tex } = self.textures.get(texture).unwrap();
-        unsafe { gl::BindTexture(gl::TEXTURE_2D, *tex); }
+    // Flips pixels to bottom up order and premultiplies RGBA pixels. sRGB pixels are
+    // premultiplied in linear space, and decoded entirely where sRGB textures are missing.
+    fn tex_data(&self, format: TexFormat, options: TexOptions, width: usize, pixels: &[u8]) -> Vec<u8> {
+        let mut data = flip(pixels, width * format.bytes_per_pixel());
+        let decode = self.api == GlApi::Gles2;
         match format {
-            TexFormat::RGBA => {
-                if flipped.len() != width * height * 4 { panic!() }
-                unsafe {
-                    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
-                    gl::TexSubImage2D(gl::TEXTURE_2D, 0, x as i32, y as i32, width as i32, height as i32, gl::RGBA, gl::UNSIGNED_INT_8_8_8_8, flipped.as_ptr() as *const std::ffi::c_void);
+            TexFormat::RGBA if !options.premultiplied => {
+                for pixel in data.chunks_mut(4) {
+                    let alpha = pixel[3] as u32;
+                    for channel in pixel[0..3].iter_mut() {
+                        *channel = ((*channel as u32 * alpha + 127) / 255) as u8;
+                    }
                 }
             }
-            TexFormat::A => {
-                if flipped.len() != width * height { panic!() }
-                unsafe {
-                    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
-                    gl::TexSubImage2D(gl::TEXTURE_2D, 0, x as i32, y as i32, width as i32, height as i32, gl::RED, gl::UNSIGNED_BYTE, flipped.as_ptr() as *const std::ffi::c_void);
+            TexFormat::SRGBA if !options.premultiplied || decode => {
+                for pixel in data.chunks_mut(4) {
+                    let alpha = if options.premultiplied { 1.0 } else { pixel[3] as f32 / 255.0 };
+                    for channel in pixel[0..3].iter_mut() {
+                        let linear = srgb_to_linear(*channel as f32 / 255.0) * alpha;
+                        let value = if decode { linear } else { linear_to_srgb(linear) };
+                        *channel = (value * 255.0).round() as u8;
+                    }
                 }
             }
+            _ => {}
         }
+        data
+    }
+
+    pub fn create_render_target(&mut self, width: usize, height: usize) -> Result<RenderTargetId, RendererError> {
+        self.new_render_target(width, height, 1)
+    }
+
+    // A render target with multiple samples per pixel, which has no texture and is resolved
+    // by blitting it with blit_render_target. The sample count is limited to what the
+    // context supports. Not available on OpenGL ES 2.
+    pub fn create_render_target_multisampled(&mut self, width: usize, height: usize, samples: u32) -> Result<RenderTargetId, RendererError> {
+        if self.api == GlApi::Gles2 {
+            return Err(RendererError::Unsupported("multisampling"));
+        }
+        let mut max_samples: GLint = 0;
+        unsafe { gl::GetIntegerv(gl::MAX_SAMPLES, &mut max_samples); }
+        self.new_render_target(width, height, samples.min(max_samples.max(1) as u32))
+    }
+
+    fn new_render_target(&mut self, width: usize, height: usize, samples: u32) -> Result<RenderTargetId, RendererError> {
+        // with FRAMEBUFFER_SRGB, an sRGB texture is encoded on write like the window is.
+        // Elsewhere the shaders encode what they write, which draw_tex decodes again.
+        let internal_format = match self.api {
+            GlApi::Gl33 => gl::SRGB8_ALPHA8,
+            GlApi::Gles3 => gl::RGBA8,
+            GlApi::Gles2 => gl::RGBA,
+        };
+        let format = match self.api {
+            GlApi::Gl33 => TexFormat::SRGBA,
+            _ => TexFormat::RGBA,
+        };
+        let mut max_size: GLint = 0;
+        unsafe { gl::GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut max_size); }
+        if width == 0 || height == 0 || width > max_size as usize || height > max_size as usize {
+            return Err(RendererError::TextureSize);
+        }
+
+        let mut tex: GLuint = 0;
+        let mut color: GLuint = 0;
+        let mut fbo: GLuint = 0;
+        let mut stencil: GLuint = 0;
+        unsafe {
+            while gl::GetError() != gl::NO_ERROR {}
+
+            gl::GenFramebuffers(1, &mut fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+
+            if samples > 1 {
+                gl::GenRenderbuffers(1, &mut color);
+                gl::BindRenderbuffer(gl::RENDERBUFFER, color);
+                gl::RenderbufferStorageMultisample(gl::RENDERBUFFER, samples as i32, internal_format, width as i32, height as i32);
+                gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, color);
+            } else {
+                gl::GenTextures(1, &mut tex);
+                gl::BindTexture(gl::TEXTURE_2D, tex);
+                gl::TexImage2D(gl::TEXTURE_2D, 0, internal_format as GLint, width as i32, height as i32, 0, gl::RGBA, gl::UNSIGNED_BYTE, std::ptr::null());
+                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, tex, 0);
+            }
+
+            // OpenGL ES 2 has no packed depth and stencil format
+            gl::GenRenderbuffers(1, &mut stencil);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, stencil);
+            if self.api == GlApi::Gles2 {
+                gl::RenderbufferStorage(gl::RENDERBUFFER, gl::STENCIL_INDEX8, width as i32, height as i32);
+                gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::STENCIL_ATTACHMENT, gl::RENDERBUFFER, stencil);
+            } else {
+                if samples > 1 {
+                    gl::RenderbufferStorageMultisample(gl::RENDERBUFFER, samples as i32, gl::DEPTH24_STENCIL8, width as i32, height as i32);
+                } else {
+                    gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH24_STENCIL8, width as i32, height as i32);
+                }
+                gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_STENCIL_ATTACHMENT, gl::RENDERBUFFER, stencil);
+            }
+
+            let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+            let error = gl::GetError();
+            self.bind_framebuffer();
+
+            if status != gl::FRAMEBUFFER_COMPLETE || error != gl::NO_ERROR {
+                gl::DeleteFramebuffers(1, &fbo);
+                gl::DeleteRenderbuffers(1, &stencil);
+                gl::DeleteRenderbuffers(1, &color);
+                gl::DeleteTextures(1, &tex);
+                return Err(if error != gl::NO_ERROR { RendererError::Gl(error) } else { RendererError::IncompleteFramebuffer(status) });
+            }
+        }
+        let tex = if samples > 1 { None } else {
+            // blending into the target leaves its color premultiplied
+            let options = TexOptions { premultiplied: true, ..TexOptions::default() };
+            let encoded = self.api != GlApi::Gl33;
+            Some(self.textures.insert(Texture { format, options, encoded, tex, width, height }))
+        };
+        Ok(self.render_targets.insert(RenderTarget { fbo, stencil, color, tex, width, height }))
+    }
+
+    // The texture holding what was drawn into a render target, for use with draw_tex.
+    // Multisampled targets have none.
+    pub fn render_target_tex(&self, target: RenderTargetId) -> Option<TexId> {
+        self.render_targets.get(target).and_then(|target| target.tex)
+    }
+
+    // Directs drawing into a render target, or back to the default framebuffer for None.
+    // The viewport is set to cover the whole target.
+    pub fn bind_render_target(&mut self, target: Option<RenderTargetId>) -> Result<(), RendererError> {
+        if let Some(target) = target {
+            if self.render_targets.get(target).is_none() { return Err(RendererError::InvalidRenderTarget); }
+        }
+        if self.target.is_none() && target.is_some() {
+            unsafe { gl::GetIntegerv(gl::VIEWPORT, self.default_viewport.as_mut_ptr()); }
+        }
+        self.target = target;
+        unsafe { self.bind_framebuffer(); }
+        Ok(())
+    }
+
+    pub fn render_target(&self) -> Option<RenderTargetId> {
+        self.target
+    }
+
+    // Copies a render target into the bound framebuffer, with their bottom left corners
+    // aligned, resolving it if it is multisampled. Needs framebuffer blits, which OpenGL ES 2
+    // lacks.
+    pub fn blit_render_target(&mut self, source: RenderTargetId) -> Result<(), RendererError> {
+        if self.api == GlApi::Gles2 {
+            return Err(RendererError::Unsupported("blitting a framebuffer"));
+        }
+        let &RenderTarget { fbo, width, height, .. } = self.render_targets.get(source).ok_or(RendererError::InvalidRenderTarget)?;
+        let target = self.target.and_then(|target| self.render_targets.get(target)).map_or(0, |target| target.fbo);
+        let start = Instant::now();
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, fbo);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, target);
+            gl::BlitFramebuffer(0, 0, width as GLint, height as GLint, 0, 0, width as GLint, height as GLint, gl::COLOR_BUFFER_BIT, gl::NEAREST);
+            self.bind_framebuffer();
+        }
+        self.submit_time += start.elapsed();
+        Ok(())
+    }
+
+    // Restricts drawing and clearing to a rectangle of the bound framebuffer, given as x, y,
+    // width and height in pixels from the top left corner, or lifts the restriction.
+    pub fn scissor(&mut self, rect: Option<[i32; 4]>) {
+        unsafe {
+            if let Some([x, y, width, height]) = rect {
+                let mut viewport: [GLint; 4] = [0; 4];
+                gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+                gl::Enable(gl::SCISSOR_TEST);
+                gl::Scissor(x, viewport[3] - (y + height), width, height);
+            } else {
+                gl::Disable(gl::SCISSOR_TEST);
+            }
+        }
+    }
+
+    unsafe fn bind_framebuffer(&self) {
+        if let Some(target) = self.target.and_then(|target| self.render_targets.get(target)) {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, target.fbo);
+            gl::Viewport(0, 0, target.width as i32, target.height as i32);
+        } else {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            if self.default_viewport[2] > 0 {
+                let [x, y, width, height] = self.default_viewport;
+                gl::Viewport(x, y, width, height);
+            }
+        }
+    }
+
+    pub fn delete_render_target(&mut self, target: RenderTargetId) {
+        if self.target == Some(target) {
+            let _ = self.bind_render_target(None);
+        }
+        let RenderTarget { fbo, stencil, color, tex, .. } = self.render_targets.remove(target).unwrap();
+        if let Some(tex) = tex {
+            self.delete_tex(tex);
+        }
+        unsafe {
+            gl::DeleteFramebuffers(1, &fbo);
+            gl::DeleteRenderbuffers(1, &stencil);
+            gl::DeleteRenderbuffers(1, &color);
+        }
+    }
+
+    // Reads back a region of the bound framebuffer as RGBA bytes, with rows from top to
+    // bottom and (x, y) measured from the top left corner.
+    pub fn read_pixels(&mut self, x: usize, y: usize, width: usize, height: usize) -> Result<Vec<u8>, RendererError> {
+        let mut viewport: [GLint; 4] = [0; 4];
+        unsafe { gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr()); }
+        let (target_width, target_height) = match self.target.and_then(|target| self.render_targets.get(target)) {
+            Some(target) => (target.width, target.height),
+            None => (viewport[2].max(0) as usize, viewport[3].max(0) as usize),
+        };
+        if x + width > target_width || y + height > target_height {
+            return Err(RendererError::OutOfBounds);
+        }
+        if width == 0 || height == 0 { return Ok(Vec::new()); }
+        let mut pixels = vec![0; width * height * 4];
+        unsafe {
+            let gl_y = viewport[3] - (y + height) as GLint;
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 4);
+            gl::ReadPixels(x as GLint, gl_y, width as GLint, height as GLint, gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_mut_ptr() as *mut std::ffi::c_void);
+        }
+        Ok(flip(&pixels, width * 4))
     }
 
     pub fn delete_tex(&mut self, texture: TexId) {
@@ -346,22 +1257,71 @@ impl Renderer {
 
 impl Drop for Renderer {
     fn drop(&mut self) {
+        let programs = [self.prog, self.prog_tex_rgba, self.prog_tex_encoded, self.prog_tex_a, self.prog_tex_lcd, self.prog_tex_sdf, self.prog_shape];
         unsafe {
-            gl::DeleteProgram(self.prog);
-            gl::DeleteProgram(self.prog_tex_rgba);
-            gl::DeleteProgram(self.prog_tex_a);
+            // zero handles were never created, as in a headless renderer
+            for prog in programs.iter().chain(self.shaders.iter()) {
+                if *prog != 0 {
+                    gl::DeleteProgram(*prog);
+                }
+            }
 
             for Texture { tex, .. } in self.textures.iter() {
                 gl::DeleteTextures(1, tex);
             }
+            for RenderTarget { fbo, stencil, color, .. } in self.render_targets.iter() {
+                gl::DeleteFramebuffers(1, fbo);
+                gl::DeleteRenderbuffers(1, stencil);
+                gl::DeleteRenderbuffers(1, color);
+            }
+
+            #[cfg(feature = "gpu-timer")]
+            {
+                if self.timer_queries != [0; 2] {
+                    gl::DeleteQueries(2, self.timer_queries.as_ptr());
+                }
+            }
         }
     }
 }
 
-fn flip(pixels: &[u8], width: usize) -> Vec<u8> {
+// Internal format, pixel format and unpack alignment of a texture format. OpenGL ES 2 only
+// has unsized formats and no single channel red format.
+fn tex_format(api: GlApi, format: TexFormat) -> (GLenum, GLenum, GLint) {
+    match (api, format) {
+        (GlApi::Gles2, TexFormat::RGBA) | (GlApi::Gles2, TexFormat::SRGBA) => (gl::RGBA, gl::RGBA, 4),
+        (GlApi::Gles2, TexFormat::A) | (GlApi::Gles2, TexFormat::SDF) => (LUMINANCE, LUMINANCE, 1),
+        (GlApi::Gles2, TexFormat::LCD) => (gl::RGB, gl::RGB, 1),
+        (_, TexFormat::RGBA) => (gl::RGBA8, gl::RGBA, 4),
+        (_, TexFormat::SRGBA) => (gl::SRGB8_ALPHA8, gl::RGBA, 4),
+        (_, TexFormat::A) | (_, TexFormat::SDF) => (gl::R8, gl::RED, 1),
+        (_, TexFormat::LCD) => (gl::RGB8, gl::RGB, 1),
+    }
+}
+
+pub fn srgb_to_linear(x: f32) -> f32 {
+    if x < 0.04045 { x / 12.92 } else { ((x + 0.055) / 1.055).powf(2.4) }
+}
+
+pub fn linear_to_srgb(x: f32) -> f32 {
+    if x < 0.0031308 { x * 12.92 } else { 1.055 * x.powf(1.0 / 2.4) - 0.055 }
+}
+
+fn flip(pixels: &[u8], stride: usize) -> Vec<u8> {
     let mut flipped: Vec<u8> = Vec::with_capacity(pixels.len());
-    for chunk in pixels.rchunks(width) {
+    for chunk in pixels.rchunks(stride) {
         flipped.extend(chunk);
     }
     flipped
 }
+
+#[test]
+fn test_parse_version() {
+    assert_eq!(parse_version("3.3.0 NVIDIA 390.77"), Some((3, 3, false)));
+    assert_eq!(parse_version("4.5 (Core Profile) Mesa 18.0.5"), Some((4, 5, false)));
+    assert_eq!(parse_version("OpenGL ES 3.2 Mesa 18.0.5"), Some((3, 2, true)));
+    assert_eq!(parse_version("OpenGL ES-CM 1.1"), Some((1, 1, true)));
+    assert_eq!(parse_version("OpenGL ES 2.0 (WebGL 1.0 (OpenGL ES 2.0 Chromium))"), Some((2, 0, true)));
+    assert_eq!(parse_version("WebGL 2.0"), Some((3, 0, true)));
+    assert_eq!(parse_version(""), None);
+}
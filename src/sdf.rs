@@ -0,0 +1,96 @@
+// Converts a coverage bitmap into a signed distance field with `spread` texels of padding
+// on each side. Distances are measured in texels, clamped to `spread` and mapped so that
+// the outline lies at 128, the inside above it and the outside below it.
+pub fn distance_field(coverage: &[u8], width: usize, height: usize, spread: usize) -> Vec<u8> {
+    let field_width = width + 2 * spread;
+    let field_height = height + 2 * spread;
+    let radius = spread as isize;
+
+    let inside = |x: isize, y: isize| -> bool {
+        if x < 0 || y < 0 || x >= width as isize || y >= height as isize {
+            false
+        } else {
+            coverage[y as usize * width + x as usize] >= 128
+        }
+    };
+
+    let mut field = Vec::with_capacity(field_width * field_height);
+    for y in 0..field_height as isize {
+        for x in 0..field_width as isize {
+            let (px, py) = (x - radius, y - radius);
+            let state = inside(px, py);
+            // search for the nearest texel on the other side of the outline
+            let mut nearest = (radius * radius * 2 + 1) as f32;
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    if inside(px + dx, py + dy) != state {
+                        nearest = nearest.min((dx * dx + dy * dy) as f32);
+                    }
+                }
+            }
+            // the outline lies halfway between the two texel centers
+            let dist = (nearest.sqrt() - 0.5).min(spread as f32);
+            let signed = if state { dist } else { -dist };
+            let value = 0.5 + 0.5 * signed / spread as f32;
+            field.push((value.max(0.0).min(1.0) * 255.0).round() as u8);
+        }
+    }
+    field
+}
+
+// Evaluates a distance field into coverage the way the SDF fragment shader does when the
+// field is drawn at its own size, for drivers without that shader.
+pub fn field_coverage(field: &[u8], spread: usize) -> Vec<u8> {
+    // the shader's smoothstep spans 0.7 of the change in distance across one pixel, which is
+    // 0.5 / spread in the field's encoding
+    let width = 0.7 * 0.5 / spread as f32;
+    field.iter().map(|&value| {
+        let t = ((value as f32 / 255.0 - (0.5 - width)) / (2.0 * width)).max(0.0).min(1.0);
+        ((t * t * (3.0 - 2.0 * t)) * 255.0).round() as u8
+    }).collect()
+}
+
+#[test]
+fn test_padding() {
+    let coverage = [255; 4 * 4];
+    let field = distance_field(&coverage, 4, 4, 2);
+    assert_eq!(field.len(), 8 * 8);
+    assert_eq!(field[0], 0);
+}
+
+#[test]
+fn test_outline() {
+    let mut coverage = [0; 8 * 8];
+    for y in 2..6 {
+        for x in 2..6 {
+            coverage[y * 8 + x] = 255;
+        }
+    }
+    let field = distance_field(&coverage, 8, 8, 2);
+    let at = |x: usize, y: usize| field[(y + 2) * 12 + x + 2];
+    // texels on either side of the edge straddle the midpoint
+    assert!(at(2, 4) > 128);
+    assert!(at(1, 4) < 128);
+    // distances increase monotonically away from the edge
+    assert!(at(3, 4) > at(2, 4));
+    assert!(at(0, 4) < at(1, 4));
+}
+
+#[test]
+fn test_field_coverage() {
+    let mut coverage = [0; 8 * 8];
+    for y in 2..6 {
+        for x in 2..6 {
+            coverage[y * 8 + x] = 255;
+        }
+    }
+    let field = distance_field(&coverage, 8, 8, 2);
+    let evaluated = field_coverage(&field, 2);
+    let at = |x: usize, y: usize| evaluated[(y + 2) * 12 + x + 2];
+    assert_eq!(evaluated.len(), field.len());
+    assert_eq!(at(4, 4), 255);
+    assert_eq!(at(0, 4), 0);
+    // the outline falls between the texels on either side of the edge
+    assert!(at(2, 4) > 128);
+    assert!(at(1, 4) < 128);
+}
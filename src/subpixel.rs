@@ -0,0 +1,58 @@
+// Weights of the filter applied across neighboring subpixels to reduce color fringing.
+const FILTER: [f32; 5] = [1.0 / 9.0, 2.0 / 9.0, 3.0 / 9.0, 2.0 / 9.0, 1.0 / 9.0];
+
+// Top left corner, in pixels, of the subpixel bitmap for a glyph whose triple size bitmap
+// has its top left corner at (left, top). One pixel of padding on each side leaves room
+// for the filter to spread horizontally.
+pub fn subpixel_origin(left: i32, top: i32) -> (i32, i32) {
+    (left.div_euclid(3) - 1, top.div_euclid(3))
+}
+
+// Converts the coverage of a glyph rasterized at three times its size into RGB coverage at
+// its original size, with each channel sampling one horizontal subpixel. Rows of the triple
+// size bitmap are averaged in groups of three to bring it back to the original height.
+pub fn subpixel_coverage(coverage: &[u8], width: usize, height: usize, left: i32, top: i32) -> (usize, usize, Vec<u8>) {
+    let (x0, y0) = subpixel_origin(left, top);
+    let x1 = (left + width as i32 + 2).div_euclid(3) + 1;
+    let y1 = (top + height as i32 + 2).div_euclid(3);
+    let (out_width, out_height) = ((x1 - x0) as usize, (y1 - y0) as usize);
+
+    let sample = |x: i32, y: i32| -> f32 {
+        let col = x - left;
+        if col < 0 || col >= width as i32 { return 0.0; }
+        let mut sum = 0;
+        for row in (3 * y - top)..(3 * y - top + 3) {
+            if row >= 0 && row < height as i32 {
+                sum += coverage[row as usize * width + col as usize] as u32;
+            }
+        }
+        sum as f32 / 3.0
+    };
+
+    let mut data = Vec::with_capacity(out_width * out_height * 3);
+    for y in y0..y1 {
+        for x in x0..x1 {
+            for channel in 0..3 {
+                let subpixel = 3 * x + channel;
+                let mut value = 0.0;
+                for (i, weight) in FILTER.iter().enumerate() {
+                    value += weight * sample(subpixel + i as i32 - 2, y);
+                }
+                data.push(value.round().min(255.0) as u8);
+            }
+        }
+    }
+    (out_width, out_height, data)
+}
+
+#[test]
+fn test_coverage_size() {
+    let coverage = [255; 9 * 6];
+    let (width, height, data) = subpixel_coverage(&coverage, 9, 6, 3, -6);
+    assert_eq!((width, height), (5, 2));
+    assert_eq!(data.len(), width * height * 3);
+    // the pixels fully covered by the glyph are opaque in every channel
+    assert_eq!(&data[3 * 2..3 * 3], &[255, 255, 255]);
+    // the padding only receives coverage spread by the filter
+    assert_eq!(data[0], 0);
+}
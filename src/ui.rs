@@ -1,42 +1,59 @@
 use crate::alloc::*;
 use crate::graphics::*;
+use crate::font::FontId;
+use crate::render::RendererError;
 
 use std::f32;
 use std::borrow::Cow;
 use std::collections::{HashSet, HashMap};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 macro_rules! id {
     () => { { static ID: u8 = 0; &ID as *const u8 as usize as u64 } }
 }
 
+const LAYOUT_CACHE_ENTRIES: usize = 4;
+
 pub struct UI {
     graphics: Graphics,
 
     tree: Vec<Node>,
     map: HashMap<u64, usize>,
+    // subtrees laid out earlier, by the id their root had before layout, most recent last
+    layout_cache: HashMap<u64, Vec<CachedLayout>>,
     hover: HashSet<usize>,
     drag: Option<u64>,
 
     cursor: (f32, f32),
     modifiers: Modifiers,
     mouse: MouseState,
+    needs_frame: bool,
+    last_frame: Option<Instant>,
 }
 
 impl UI {
-    pub fn new(dpi_factor: f32) -> UI {
+    pub fn new(dpi_factor: f32) -> Result<UI, RendererError> {
+        Ok(UI::with_graphics(Graphics::new(dpi_factor)?))
+    }
+
+    fn with_graphics(graphics: Graphics) -> UI {
         UI {
-            graphics: Graphics::new(dpi_factor),
+            graphics,
 
             tree: Vec::new(),
             map: HashMap::new(),
+            layout_cache: HashMap::new(),
             hover: HashSet::new(),
             drag: None,
 
             cursor: (-1.0, -1.0),
             modifiers: Modifiers::default(),
             mouse: MouseState::default(),
+            needs_frame: true,
+            last_frame: None,
         }
     }
 
@@ -44,7 +61,41 @@ impl UI {
         &mut self.graphics
     }
 
-    pub fn run(&mut self, width: f32, height: f32, root: &dyn Widget) {
+    // Whether input was received or a widget asked to be redrawn since the last frame. State
+    // held by the host is not tracked, so a host that changes it must run a frame regardless.
+    pub fn needs_frame(&self) -> bool {
+        self.needs_frame
+    }
+
+    // Requests a full redraw, for when the window was resized or its contents were lost.
+    pub fn redraw(&mut self) {
+        self.needs_frame = true;
+        self.graphics.invalidate();
+    }
+
+    // Drops all cached layout, for when something widgets don't hash has changed, such as
+    // the fonts.
+    pub fn invalidate_layout(&mut self) {
+        self.layout_cache = HashMap::new();
+    }
+
+    // Returns the frame's stats, including whether it differs from the last one and needs
+    // to be presented.
+    pub fn run(&mut self, width: f32, height: f32, root: &dyn Widget) -> FrameStats {
+        self.needs_frame = false;
+        let start = Instant::now();
+        let interval = self.last_frame.map_or(Duration::default(), |last| start - last);
+        self.last_frame = Some(start);
+        self.layout(width, height, root);
+        let layout = start.elapsed();
+        let start = Instant::now();
+        root.render(RenderContext { ui: self, index: 0 });
+        let render = start.elapsed();
+        self.graphics.draw(width, height);
+        FrameStats { interval, layout, render, ..self.graphics.frame_stats() }
+    }
+
+    fn layout(&mut self, width: f32, height: f32, root: &dyn Widget) {
         self.tree = vec![Node {
             id: 0,
             start: 0,
@@ -55,17 +106,22 @@ impl UI {
         let hasher = DefaultHasher::new();
         let mut context = LayoutContext { ui: self, index: 0, parent_hasher: &hasher, hasher: hasher.clone() };
         context.key(0);
-        root.layout(context, width, height);
+        context.layout_widget(root, Constraints::loose(width, height));
+        // subtrees that are no longer in the tree are dropped from the cache
+        let ids: HashSet<u64> = self.tree.iter().map(|node| node.id).collect();
+        self.layout_cache.retain(|_, entries| {
+            entries.retain(|cached| ids.contains(&cached.node.id));
+            !entries.is_empty()
+        });
         self.update_offsets(0, 0.0, 0.0);
         self.hover = HashSet::new();
         self.update_hover(0);
         self.update_map(0);
-        root.render(RenderContext { ui: self, index: 0 });
-        self.graphics.draw(width, height);
     }
 
     pub fn cursor(&mut self, x: f32, y: f32) {
         self.cursor = (x, y);
+        self.needs_frame = true;
     }
 
     pub fn modifiers(&mut self, modifiers: Modifiers) {
@@ -73,6 +129,7 @@ impl UI {
     }
 
     pub fn input(&mut self, input: Input) {
+        self.needs_frame = true;
         match input {
             Input::MouseDown(..) | Input::MouseUp(..) | Input::Scroll(..) => {
                 if let Some(i) = self.drag.and_then(|id| self.map.get(&id)) {
@@ -136,15 +193,62 @@ impl UI {
     }
 
     fn fire(&mut self, i: usize, input: Input) -> bool {
-        if self.tree[i].handler.is_some() {
-            let handler = self.tree[i].handler.take().unwrap();
-            let result = handler(EventContext { ui: self, index: i }, input);
-            self.tree[i].handler = Some(handler);
-            result
+        if let Some(handler) = self.tree[i].handler.clone() {
+            handler(EventContext { ui: self, index: i }, input)
         } else {
             false
         }
     }
+
+    fn constrain(&mut self, i: usize, constraints: Constraints) {
+        let rect = &mut self.tree[i].rect;
+        let (width, height) = constraints.constrain(rect.width, rect.height);
+        rect.width = width;
+        rect.height = height;
+    }
+
+    // Copies a cached subtree to the end of the tree and makes node i its root, if it was
+    // laid out with the same hash.
+    fn restore_layout(&mut self, i: usize, key: u64, hash: u64) -> bool {
+        let cached = match self.layout_cache.get(&key).and_then(|entries| entries.iter().find(|cached| cached.hash == hash)) {
+            Some(cached) => cached,
+            None => return false,
+        };
+        let first = self.tree.len();
+        let rebase = |node: &Node| Node { start: if node.len > 0 { node.start + first } else { 0 }, ..node.clone() };
+        self.tree.extend(cached.nodes.iter().map(rebase));
+        self.tree[i] = rebase(&cached.node);
+        true
+    }
+
+    // Caches the subtree just laid out under node i. A widget laid out under several
+    // constraints, such as by a parent measuring it first, keeps an entry for each of the
+    // most recent ones.
+    fn store_layout(&mut self, i: usize, key: u64, hash: u64) {
+        let mut nodes = Vec::new();
+        let node = self.copy_subtree(i, &mut nodes);
+        let entries = self.layout_cache.entry(key).or_insert_with(Vec::new);
+        entries.retain(|cached| cached.hash != hash);
+        if entries.len() == LAYOUT_CACHE_ENTRIES {
+            entries.remove(0);
+        }
+        entries.push(CachedLayout { hash, node, nodes });
+    }
+
+    // Copies the nodes reachable from node i, leaving out any that were laid out and then
+    // abandoned, with each node's children next to each other as in the tree.
+    fn copy_subtree(&self, i: usize, nodes: &mut Vec<Node>) -> Node {
+        let node = &self.tree[i];
+        if node.len == 0 {
+            return Node { start: 0, ..node.clone() };
+        }
+        let start = nodes.len();
+        nodes.extend(self.tree[node.start..node.start + node.len].iter().cloned());
+        for child in 0..node.len {
+            nodes[start + child] = self.copy_subtree(node.start + child, nodes);
+        }
+        Node { start, ..node.clone() }
+    }
 }
 
 pub struct LayoutContext<'a> {
@@ -181,6 +285,33 @@ impl<'a> LayoutContext<'a> {
         context
     }
 
+    // Lays out a child, reusing its layout from an earlier frame if the child is at the same
+    // place in the tree and neither its hash_layout nor its constraints have changed.
+    pub fn layout_child(&mut self, index: usize, child: &dyn Widget, constraints: Constraints) {
+        self.child(index).layout_widget(child, constraints);
+    }
+
+    fn layout_widget(self, widget: &dyn Widget, constraints: Constraints) {
+        let i = self.index;
+        let mut hasher = DefaultHasher::new();
+        if widget.hash_layout(&mut hasher) {
+            constraints.hash(&mut hasher);
+            let hash = hasher.finish();
+
+            // the widget may change its id with key, so the cache goes by the id it starts with
+            let key = self.ui.tree[i].id;
+            if self.ui.restore_layout(i, key, hash) {
+                return;
+            }
+            widget.layout(LayoutContext { ui: &mut *self.ui, index: i, parent_hasher: self.parent_hasher, hasher: self.hasher.clone() }, constraints);
+            self.ui.constrain(i, constraints);
+            self.ui.store_layout(i, key, hash);
+        } else {
+            widget.layout(LayoutContext { ui: &mut *self.ui, index: i, parent_hasher: self.parent_hasher, hasher: self.hasher.clone() }, constraints);
+            self.ui.constrain(i, constraints);
+        }
+    }
+
     pub fn offset_child(&mut self, index: usize, x: f32, y: f32) {
         let (len, start) = (self.ui.tree[self.index].len, self.ui.tree[self.index].start);
         assert!(index < len, "child index out of range");
@@ -213,7 +344,7 @@ impl<'a> LayoutContext<'a> {
     }
 
     pub fn listen<F>(&mut self, f: F) where F: Fn(EventContext, Input) -> bool + 'static {
-        self.ui.tree[self.index].handler = Some(Box::new(f));
+        self.ui.tree[self.index].handler = Some(Rc::new(f));
     }
 }
 
@@ -246,7 +377,12 @@ impl<'a> RenderContext<'a> {
     }
 
     pub fn listen<F>(&mut self, f: F) where F: Fn(EventContext, Input) -> bool + 'static {
-        self.ui.tree[self.index].handler = Some(Box::new(f));
+        self.ui.tree[self.index].handler = Some(Rc::new(f));
+    }
+
+    // Asks for another frame after this one, for widgets that change without input.
+    pub fn request_frame(&mut self) {
+        self.ui.needs_frame = true;
     }
 }
 
@@ -281,12 +417,23 @@ impl<'a> EventContext<'a> {
     }
 }
 
+#[derive(Clone)]
 pub struct Node {
     id: u64,
     start: usize,
     len: usize,
     rect: Rect,
-    handler: Option<Box<Fn(EventContext, Input) -> bool>>,
+    handler: Option<Rc<Fn(EventContext, Input) -> bool>>,
+}
+
+// Rects in the cache are relative to their parents, as the tree's are until
+// update_offsets, and starts are relative to the first of the nodes. Every cached subtree
+// holds a copy of all of its nodes, so the cache takes memory in proportion to the number
+// of nodes times the depth of the tree, times at most LAYOUT_CACHE_ENTRIES.
+struct CachedLayout {
+    hash: u64,
+    node: Node,
+    nodes: Vec<Node>,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -304,9 +451,70 @@ impl Rect {
     }
 }
 
+// The range of sizes, as (width, height), a widget may take. Maximums may be infinite, in
+// which case the widget should take the size of its contents.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Constraints {
+    pub min: (f32, f32),
+    pub max: (f32, f32),
+}
+
+impl Constraints {
+    pub fn tight(width: f32, height: f32) -> Constraints {
+        Constraints { min: (width, height), max: (width, height) }
+    }
+
+    pub fn loose(width: f32, height: f32) -> Constraints {
+        Constraints { min: (0.0, 0.0), max: (width, height) }
+    }
+
+    pub fn loosen(&self) -> Constraints {
+        Constraints { min: (0.0, 0.0), max: self.max }
+    }
+
+    // The size within the constraints closest to the given one.
+    pub fn constrain(&self, width: f32, height: f32) -> (f32, f32) {
+        (width.max(self.min.0).min(self.max.0), height.max(self.min.1).min(self.max.1))
+    }
+
+    // Shrinks the constraints by a padding, without going below zero.
+    pub fn deflate(&self, width: f32, height: f32) -> Constraints {
+        let min = ((self.min.0 - width).max(0.0), (self.min.1 - height).max(0.0));
+        Constraints { min, max: ((self.max.0 - width).max(min.0), (self.max.1 - height).max(min.1)) }
+    }
+
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        for x in [self.min.0, self.min.1, self.max.0, self.max.1].iter() {
+            x.to_bits().hash(hasher);
+        }
+    }
+}
+
 pub trait Widget {
-    fn layout(&self, context: LayoutContext, max_width: f32, max_height: f32);
+    // Must end with a size within the constraints, which LayoutContext::layout_child
+    // enforces by clamping.
+    fn layout(&self, context: LayoutContext, constraints: Constraints);
     fn render(&self, context: RenderContext);
+
+    // The smallest width the widget can take without its contents overflowing, and the
+    // width beyond which it doesn't get any shorter, for the given height, which may be
+    // infinite. Lets parents size children by their contents without laying them out.
+    fn intrinsic_width(&self, _graphics: &Graphics, _height: f32) -> (f32, f32) {
+        (0.0, 0.0)
+    }
+
+    // Like intrinsic_width, with the axes swapped.
+    fn intrinsic_height(&self, _graphics: &Graphics, _width: f32) -> (f32, f32) {
+        (0.0, 0.0)
+    }
+
+    // Hashes everything the layout of the widget and its children depends on, including
+    // anything captured by handlers set during layout, so that it can be reused across
+    // frames by LayoutContext::layout_child. Returns false for widgets that must be laid out
+    // every frame, such as those whose layout depends on drag state.
+    fn hash_layout(&self, _hasher: &mut DefaultHasher) -> bool {
+        false
+    }
 }
 
 
@@ -323,18 +531,41 @@ impl<'a> Row<'a> {
 }
 
 impl<'a> Widget for Row<'a> {
-    fn layout(&self, mut context: LayoutContext, max_width: f32, max_height: f32) {
+    fn layout(&self, mut context: LayoutContext, constraints: Constraints) {
         context.children(self.children.len());
         let mut x: f32 = 0.0;
         let mut height: f32 = 0.0;
         for (i, child) in self.children.iter().enumerate() {
-            child.layout(context.child(i), f32::INFINITY, max_height);
+            context.layout_child(i, *child, Constraints::loose(f32::INFINITY, constraints.max.1));
             context.offset_child(i, x, 0.0);
             let (child_width, child_height) = context.child_size(i);
             x += child_width + self.spacing;
             height = height.max(child_height);
         }
-        context.size(x - self.spacing, height)
+        let (width, height) = constraints.constrain(x - self.spacing, height);
+        context.size(width, height)
+    }
+
+    fn intrinsic_width(&self, graphics: &Graphics, height: f32) -> (f32, f32) {
+        let spacing = self.spacing * self.children.len().saturating_sub(1) as f32;
+        self.children.iter().fold((spacing, spacing), |(min, max), child| {
+            let (child_min, child_max) = child.intrinsic_width(graphics, height);
+            (min + child_min, max + child_max)
+        })
+    }
+
+    fn intrinsic_height(&self, graphics: &Graphics, _width: f32) -> (f32, f32) {
+        // children are laid out with unlimited width
+        self.children.iter().fold((0.0, 0.0), |(min, max): (f32, f32), child| {
+            let (child_min, child_max) = child.intrinsic_height(graphics, f32::INFINITY);
+            (min.max(child_min), max.max(child_max))
+        })
+    }
+
+    fn hash_layout(&self, hasher: &mut DefaultHasher) -> bool {
+        self.spacing.to_bits().hash(hasher);
+        self.children.len().hash(hasher);
+        self.children.iter().all(|child| child.hash_layout(hasher))
     }
 
     fn render(&self, mut context: RenderContext) {
@@ -345,6 +576,304 @@ impl<'a> Widget for Row<'a> {
     }
 }
 
+#[derive(Copy, Clone, PartialEq, Hash, Debug)]
+pub enum Direction {
+    Row,
+    Column,
+}
+
+// Where items go along the main axis when there is space left over in a line.
+#[derive(Copy, Clone, PartialEq, Hash, Debug)]
+pub enum Justify {
+    Start,
+    End,
+    Center,
+    SpaceBetween,
+    SpaceAround,
+    SpaceEvenly,
+}
+
+// Where items go across the main axis within their line. Stretched items are made as tall
+// as the line, or as wide for columns.
+#[derive(Copy, Clone, PartialEq, Hash, Debug)]
+pub enum Align {
+    Start,
+    End,
+    Center,
+    Stretch,
+}
+
+// A child of a Flex. Its basis is its size along the main axis before growing or
+// shrinking, and defaults to its largest intrinsic size along that axis.
+#[derive(Copy, Clone)]
+pub struct FlexItem<'a> {
+    widget: &'a dyn Widget,
+    grow: f32,
+    shrink: f32,
+    basis: Option<f32>,
+}
+
+impl<'a> FlexItem<'a> {
+    pub fn new(widget: &'a dyn Widget) -> FlexItem<'a> {
+        FlexItem { widget, grow: 0.0, shrink: 1.0, basis: None }
+    }
+
+    // An item sized only by its share of the line, regardless of its contents.
+    pub fn expanded(widget: &'a dyn Widget, flex: f32) -> FlexItem<'a> {
+        FlexItem { widget, grow: flex, shrink: flex, basis: Some(0.0) }
+    }
+
+    pub fn grow(self, grow: f32) -> FlexItem<'a> {
+        FlexItem { grow, ..self }
+    }
+
+    pub fn shrink(self, shrink: f32) -> FlexItem<'a> {
+        FlexItem { shrink, ..self }
+    }
+
+    pub fn basis(self, basis: f32) -> FlexItem<'a> {
+        FlexItem { basis: Some(basis), ..self }
+    }
+}
+
+// Lays items out in a row or column, wrapping them into more lines if enabled. Space left
+// over in a line is shared between items by their grow factors, and space missing by their
+// shrink factors weighted by their basis, and items are made exactly as long as their share.
+// The Flex takes up all of the available length along its main axis when that is finite.
+#[derive(Copy, Clone)]
+pub struct Flex<'a> {
+    direction: Direction,
+    justify: Justify,
+    align: Align,
+    wrap: bool,
+    gap: f32,
+    children: &'a [FlexItem<'a>],
+}
+
+impl<'a> Flex<'a> {
+    pub fn new(arena: &'a Arena, direction: Direction, children: &[FlexItem<'a>]) -> &'a mut Flex<'a> {
+        arena.alloc(Flex {
+            direction,
+            justify: Justify::Start,
+            align: Align::Start,
+            wrap: false,
+            gap: 0.0,
+            children: arena.alloc_slice(children),
+        })
+    }
+
+    pub fn justify(&'a mut self, justify: Justify) -> &'a mut Flex<'a> {
+        self.justify = justify;
+        self
+    }
+
+    pub fn align(&'a mut self, align: Align) -> &'a mut Flex<'a> {
+        self.align = align;
+        self
+    }
+
+    pub fn wrap(&'a mut self, wrap: bool) -> &'a mut Flex<'a> {
+        self.wrap = wrap;
+        self
+    }
+
+    // The space between items and between lines.
+    pub fn gap(&'a mut self, gap: f32) -> &'a mut Flex<'a> {
+        self.gap = gap;
+        self
+    }
+
+    // Converts between main and cross axis sizes and widths and heights, both ways.
+    fn axes(&self, a: f32, b: f32) -> (f32, f32) {
+        match self.direction {
+            Direction::Row => (a, b),
+            Direction::Column => (b, a),
+        }
+    }
+
+    fn constraints(&self, min_main: f32, max_main: f32, min_cross: f32, max_cross: f32) -> Constraints {
+        Constraints { min: self.axes(min_main, min_cross), max: self.axes(max_main, max_cross) }
+    }
+
+    // The intrinsic size of a child along the main axis, or across it when main is false.
+    fn child_intrinsic(&self, graphics: &Graphics, widget: &dyn Widget, main: bool, extent: f32) -> (f32, f32) {
+        if main == (self.direction == Direction::Row) {
+            widget.intrinsic_width(graphics, extent)
+        } else {
+            widget.intrinsic_height(graphics, extent)
+        }
+    }
+
+    fn intrinsic_main(&self, graphics: &Graphics, cross: f32) -> (f32, f32) {
+        let gaps = self.gap * self.children.len().saturating_sub(1) as f32;
+        let (mut min, mut max): (f32, f32) = (0.0, gaps);
+        for item in self.children.iter() {
+            let (item_min, item_max) = match item.basis {
+                Some(basis) => (basis, basis),
+                None => self.child_intrinsic(graphics, item.widget, true, cross),
+            };
+            // with wrapping, each item can go on a line of its own
+            min = if self.wrap { min.max(item_min) } else { min + item_min };
+            max += item_max;
+        }
+        if !self.wrap {
+            min += gaps;
+        }
+        (min, max)
+    }
+
+    fn intrinsic_cross(&self, graphics: &Graphics, _main: f32) -> (f32, f32) {
+        // approximated as if every item fit on one line at its basis
+        self.children.iter().fold((0.0, 0.0), |(min, max): (f32, f32), item| {
+            let (item_min, item_max) = self.child_intrinsic(graphics, item.widget, false, item.basis.unwrap_or(f32::INFINITY));
+            (min.max(item_min), max.max(item_max))
+        })
+    }
+}
+
+impl<'a> Widget for Flex<'a> {
+    fn layout(&self, mut context: LayoutContext, constraints: Constraints) {
+        let (min_main, min_cross) = self.axes(constraints.min.0, constraints.min.1);
+        let (max_main, max_cross) = self.axes(constraints.max.0, constraints.max.1);
+        context.children(self.children.len());
+
+        // items are measured by their intrinsic sizes, so that each is laid out only once,
+        // at its final size
+        let bases: Vec<f32> = self.children.iter().map(|item| {
+            item.basis.unwrap_or_else(|| self.child_intrinsic(context.graphics(), item.widget, true, max_cross).1)
+        }).collect();
+
+        let mut lines: Vec<(usize, usize)> = Vec::new();
+        let mut start = 0;
+        let mut length = 0.0;
+        for i in 0..self.children.len() {
+            if self.wrap && i > start && length + self.gap + bases[i] > max_main {
+                lines.push((start, i));
+                start = i;
+            }
+            length = if i == start { bases[i] } else { length + self.gap + bases[i] };
+        }
+        if start < self.children.len() {
+            lines.push((start, self.children.len()));
+        }
+
+        // sizes of the items along the main axis, and the cross size and length of each line
+        let mut slots: Vec<f32> = Vec::with_capacity(self.children.len());
+        let mut line_sizes: Vec<(f32, f32)> = Vec::with_capacity(lines.len());
+        for &(start, end) in lines.iter() {
+            let items = &self.children[start..end];
+            let gaps = self.gap * (end - start - 1) as f32;
+            let length = bases[start..end].iter().sum::<f32>() + gaps;
+            let free = if max_main.is_finite() { max_main - length } else { (min_main - length).max(0.0) };
+            let total_grow: f32 = items.iter().map(|item| item.grow).sum();
+            let total_shrink: f32 = items.iter().zip(bases[start..end].iter()).map(|(item, basis)| item.shrink * basis).sum();
+            for (item, &basis) in items.iter().zip(bases[start..end].iter()) {
+                slots.push(if free > 0.0 && total_grow > 0.0 {
+                    basis + free * item.grow / total_grow
+                } else if free < 0.0 && total_shrink > 0.0 {
+                    (basis + free * item.shrink * basis / total_shrink).max(0.0)
+                } else {
+                    basis
+                });
+            }
+
+            // stretched items need the line's cross size before they are laid out, so it is
+            // estimated from their intrinsic sizes
+            let mut line_cross: f32 = 0.0;
+            if self.align == Align::Stretch {
+                for i in start..end {
+                    let cross = self.child_intrinsic(context.graphics(), self.children[i].widget, false, slots[i]).1;
+                    line_cross = line_cross.max(cross.min(max_cross));
+                }
+            }
+            // a single line spans the whole Flex
+            if lines.len() == 1 {
+                line_cross = line_cross.max(min_cross);
+            }
+            for i in start..end {
+                let (min, max) = if self.align == Align::Stretch { (line_cross, line_cross) } else { (0.0, max_cross) };
+                context.layout_child(i, self.children[i].widget, self.constraints(slots[i], slots[i], min, max));
+                let (child_width, child_height) = context.child_size(i);
+                line_cross = line_cross.max(self.axes(child_width, child_height).1);
+            }
+            line_sizes.push((line_cross, slots[start..end].iter().sum::<f32>() + gaps));
+        }
+
+        let content_main = line_sizes.iter().fold(0.0, |max: f32, &(_, length)| max.max(length));
+        let main_size = if max_main.is_finite() { max_main } else { content_main.max(min_main) };
+        let mut cross_offset: f32 = 0.0;
+        for (line, (&(start, end), &(line_cross, length))) in lines.iter().zip(line_sizes.iter()).enumerate() {
+            let left = (main_size - length).max(0.0);
+            let count = (end - start) as f32;
+            let (mut main, spacing) = match self.justify {
+                Justify::Start => (0.0, self.gap),
+                Justify::End => (left, self.gap),
+                Justify::Center => (left / 2.0, self.gap),
+                Justify::SpaceBetween if end - start > 1 => (0.0, self.gap + left / (count - 1.0)),
+                Justify::SpaceBetween => (0.0, self.gap),
+                Justify::SpaceAround => (left / count / 2.0, self.gap + left / count),
+                Justify::SpaceEvenly => (left / (count + 1.0), self.gap + left / (count + 1.0)),
+            };
+            for i in start..end {
+                let (child_width, child_height) = context.child_size(i);
+                let cross = self.axes(child_width, child_height).1;
+                let offset = match self.align {
+                    Align::Start | Align::Stretch => 0.0,
+                    Align::End => line_cross - cross,
+                    Align::Center => (line_cross - cross) / 2.0,
+                };
+                let (x, y) = self.axes(main, cross_offset + offset);
+                context.offset_child(i, x, y);
+                main += slots[i] + spacing;
+            }
+
+            cross_offset += line_cross;
+            if line + 1 < lines.len() {
+                cross_offset += self.gap;
+            }
+        }
+
+        let (width, height) = self.axes(main_size, cross_offset);
+        let (width, height) = constraints.constrain(width, height);
+        context.size(width, height);
+    }
+
+    fn intrinsic_width(&self, graphics: &Graphics, height: f32) -> (f32, f32) {
+        match self.direction {
+            Direction::Row => self.intrinsic_main(graphics, height),
+            Direction::Column => self.intrinsic_cross(graphics, height),
+        }
+    }
+
+    fn intrinsic_height(&self, graphics: &Graphics, width: f32) -> (f32, f32) {
+        match self.direction {
+            Direction::Row => self.intrinsic_cross(graphics, width),
+            Direction::Column => self.intrinsic_main(graphics, width),
+        }
+    }
+
+    fn hash_layout(&self, hasher: &mut DefaultHasher) -> bool {
+        self.direction.hash(hasher);
+        self.justify.hash(hasher);
+        self.align.hash(hasher);
+        self.wrap.hash(hasher);
+        self.gap.to_bits().hash(hasher);
+        self.children.len().hash(hasher);
+        self.children.iter().all(|item| {
+            item.grow.to_bits().hash(hasher);
+            item.shrink.to_bits().hash(hasher);
+            item.basis.map(f32::to_bits).hash(hasher);
+            item.widget.hash_layout(hasher)
+        })
+    }
+
+    fn render(&self, mut context: RenderContext) {
+        for (i, item) in self.children.iter().enumerate() {
+            item.widget.render(context.child(i));
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct Padding<'a> {
     padding: (f32, f32, f32, f32),
@@ -365,12 +894,166 @@ impl<'a> Padding<'a> {
 }
 
 impl<'a> Widget for Padding<'a> {
-    fn layout(&self, mut context: LayoutContext, max_width: f32, max_height: f32) {
+    fn layout(&self, mut context: LayoutContext, constraints: Constraints) {
+        let (horizontal, vertical) = (self.padding.0 + self.padding.2, self.padding.1 + self.padding.3);
         context.children(1);
-        self.child.layout(context.child(0), max_width - self.padding.0 - self.padding.2, max_height - self.padding.1 - self.padding.3);
+        context.layout_child(0, self.child, constraints.deflate(horizontal, vertical));
         context.offset_child(0, self.padding.0, self.padding.1);
         let (child_width, child_height) = context.child_size(0);
-        context.size(child_width + self.padding.0 + self.padding.2, child_height + self.padding.1 + self.padding.3);
+        let (width, height) = constraints.constrain(child_width + horizontal, child_height + vertical);
+        context.size(width, height);
+    }
+
+    fn intrinsic_width(&self, graphics: &Graphics, height: f32) -> (f32, f32) {
+        let (min, max) = self.child.intrinsic_width(graphics, (height - self.padding.1 - self.padding.3).max(0.0));
+        (min + self.padding.0 + self.padding.2, max + self.padding.0 + self.padding.2)
+    }
+
+    fn intrinsic_height(&self, graphics: &Graphics, width: f32) -> (f32, f32) {
+        let (min, max) = self.child.intrinsic_height(graphics, (width - self.padding.0 - self.padding.2).max(0.0));
+        (min + self.padding.1 + self.padding.3, max + self.padding.1 + self.padding.3)
+    }
+
+    fn hash_layout(&self, hasher: &mut DefaultHasher) -> bool {
+        let (left, top, right, bottom) = self.padding;
+        for x in [left, top, right, bottom].iter() {
+            x.to_bits().hash(hasher);
+        }
+        self.child.hash_layout(hasher)
+    }
+
+    fn render(&self, mut context: RenderContext) {
+        self.child.render(context.child(0));
+    }
+}
+
+// Gives its child a fixed width and height, as far as its own constraints allow. An
+// infinite width or height makes it as large as allowed on that axis, filling its parent.
+#[derive(Copy, Clone)]
+pub struct SizedBox<'a> {
+    width: f32,
+    height: f32,
+    child: Option<&'a dyn Widget>,
+}
+
+impl<'a> SizedBox<'a> {
+    pub fn new(arena: &'a Arena, width: f32, height: f32, child: &'a dyn Widget) -> &'a SizedBox<'a> {
+        arena.alloc(SizedBox { width, height, child: Some(child) })
+    }
+
+    // Empty space, such as between items.
+    pub fn spacer(arena: &'a Arena, width: f32, height: f32) -> &'a SizedBox<'a> {
+        arena.alloc(SizedBox { width, height, child: None })
+    }
+
+    pub fn expand(arena: &'a Arena, child: &'a dyn Widget) -> &'a SizedBox<'a> {
+        SizedBox::new(arena, f32::INFINITY, f32::INFINITY, child)
+    }
+}
+
+impl<'a> Widget for SizedBox<'a> {
+    fn layout(&self, mut context: LayoutContext, constraints: Constraints) {
+        // an infinite size is left loose if the constraints are unbounded too
+        let axis = |size: f32, min: f32, max: f32| {
+            if size.is_finite() { (size.max(min).min(max), size.max(min).min(max)) } else if max.is_finite() { (max, max) } else { (min, max) }
+        };
+        let (min_width, max_width) = axis(self.width, constraints.min.0, constraints.max.0);
+        let (min_height, max_height) = axis(self.height, constraints.min.1, constraints.max.1);
+        let constraints = Constraints { min: (min_width, min_height), max: (max_width, max_height) };
+        let (width, height) = if let Some(child) = self.child {
+            context.children(1);
+            context.layout_child(0, child, constraints);
+            context.child_size(0)
+        } else {
+            (0.0, 0.0)
+        };
+        let (width, height) = constraints.constrain(width, height);
+        context.size(width, height);
+    }
+
+    fn intrinsic_width(&self, graphics: &Graphics, height: f32) -> (f32, f32) {
+        if self.width.is_finite() {
+            (self.width, self.width)
+        } else {
+            self.child.map_or((0.0, 0.0), |child| child.intrinsic_width(graphics, height))
+        }
+    }
+
+    fn intrinsic_height(&self, graphics: &Graphics, width: f32) -> (f32, f32) {
+        if self.height.is_finite() {
+            (self.height, self.height)
+        } else {
+            self.child.map_or((0.0, 0.0), |child| child.intrinsic_height(graphics, width))
+        }
+    }
+
+    fn hash_layout(&self, hasher: &mut DefaultHasher) -> bool {
+        self.width.to_bits().hash(hasher);
+        self.height.to_bits().hash(hasher);
+        self.child.map_or(true, |child| child.hash_layout(hasher))
+    }
+
+    fn render(&self, mut context: RenderContext) {
+        if let Some(child) = self.child {
+            child.render(context.child(0));
+        }
+    }
+}
+
+// Sizes its child to a ratio of width to height, as large as the constraints allow. The
+// ratio must be positive and finite.
+#[derive(Copy, Clone)]
+pub struct AspectRatio<'a> {
+    ratio: f32,
+    child: &'a dyn Widget,
+}
+
+impl<'a> AspectRatio<'a> {
+    pub fn new(arena: &'a Arena, ratio: f32, child: &'a dyn Widget) -> &'a AspectRatio<'a> {
+        assert!(ratio > 0.0 && ratio.is_finite(), "aspect ratio must be positive, got {}", ratio);
+        arena.alloc(AspectRatio { ratio, child })
+    }
+}
+
+impl<'a> Widget for AspectRatio<'a> {
+    fn layout(&self, mut context: LayoutContext, constraints: Constraints) {
+        let (mut width, mut height) = if constraints.max.0.is_finite() {
+            (constraints.max.0, constraints.max.0 / self.ratio)
+        } else if constraints.max.1.is_finite() {
+            (constraints.max.1 * self.ratio, constraints.max.1)
+        } else {
+            (constraints.min.0, constraints.min.0 / self.ratio)
+        };
+        // each adjustment keeps the ratio, until the constraints can't be met otherwise
+        if width > constraints.max.0 { width = constraints.max.0; height = width / self.ratio; }
+        if height > constraints.max.1 { height = constraints.max.1; width = height * self.ratio; }
+        if width < constraints.min.0 { width = constraints.min.0; height = width / self.ratio; }
+        if height < constraints.min.1 { height = constraints.min.1; width = height * self.ratio; }
+        let (width, height) = constraints.constrain(width, height);
+        context.children(1);
+        context.layout_child(0, self.child, Constraints::tight(width, height));
+        context.size(width, height);
+    }
+
+    fn intrinsic_width(&self, graphics: &Graphics, height: f32) -> (f32, f32) {
+        if height.is_finite() {
+            (height * self.ratio, height * self.ratio)
+        } else {
+            self.child.intrinsic_width(graphics, height)
+        }
+    }
+
+    fn intrinsic_height(&self, graphics: &Graphics, width: f32) -> (f32, f32) {
+        if width.is_finite() {
+            (width / self.ratio, width / self.ratio)
+        } else {
+            self.child.intrinsic_height(graphics, width)
+        }
+    }
+
+    fn hash_layout(&self, hasher: &mut DefaultHasher) -> bool {
+        self.ratio.to_bits().hash(hasher);
+        self.child.hash_layout(hasher)
     }
 
     fn render(&self, mut context: RenderContext) {
@@ -382,22 +1065,40 @@ impl<'a> Widget for Padding<'a> {
 pub struct Text<'a> {
     text: &'a str,
     font: FontId,
-    scale: u32,
+    scale: f32,
     color: Color,
 }
 
 impl<'a> Text<'a> {
-    pub fn new(arena: &'a Arena, text: &'a str, font: FontId, scale: u32, color: Color) -> &'a Text<'a> {
+    pub fn new(arena: &'a Arena, text: &'a str, font: FontId, scale: f32, color: Color) -> &'a Text<'a> {
         arena.alloc(Text { text, font, scale, color })
     }
 }
 
 impl<'a> Widget for Text<'a> {
-    fn layout(&self, mut context: LayoutContext, max_width: f32, max_height: f32) {
+    fn layout(&self, mut context: LayoutContext, constraints: Constraints) {
         let (width, height) = context.graphics().text_size(self.text, self.font, self.scale);
+        let (width, height) = constraints.constrain(width, height);
         context.size(width, height);
     }
 
+    fn intrinsic_width(&self, graphics: &Graphics, _height: f32) -> (f32, f32) {
+        let width = graphics.text_size(self.text, self.font, self.scale).0;
+        (width, width)
+    }
+
+    fn intrinsic_height(&self, graphics: &Graphics, _width: f32) -> (f32, f32) {
+        let height = graphics.text_size(self.text, self.font, self.scale).1;
+        (height, height)
+    }
+
+    fn hash_layout(&self, hasher: &mut DefaultHasher) -> bool {
+        self.text.hash(hasher);
+        self.font.hash(hasher);
+        self.scale.to_bits().hash(hasher);
+        true
+    }
+
     fn render(&self, mut context: RenderContext) {
         let rect = context.rect();
         context.graphics().text([rect.x, rect.y], self.text, self.font, self.scale, self.color);
@@ -416,13 +1117,25 @@ impl<'a> Button<'a> {
 }
 
 impl<'a> Widget for Button<'a> {
-    fn layout(&self, mut context: LayoutContext, max_width: f32, max_height: f32) {
+    fn layout(&self, mut context: LayoutContext, constraints: Constraints) {
         context.children(1);
-        self.child.layout(context.child(0), max_width, max_height);
+        context.layout_child(0, self.child, constraints);
         let (child_width, child_height) = context.child_size(0);
         context.size(child_width, child_height);
     }
 
+    fn intrinsic_width(&self, graphics: &Graphics, height: f32) -> (f32, f32) {
+        self.child.intrinsic_width(graphics, height)
+    }
+
+    fn intrinsic_height(&self, graphics: &Graphics, width: f32) -> (f32, f32) {
+        self.child.intrinsic_height(graphics, width)
+    }
+
+    fn hash_layout(&self, hasher: &mut DefaultHasher) -> bool {
+        self.child.hash_layout(hasher)
+    }
+
     fn render(&self, mut context: RenderContext) {
         let color = if context.drag() { Color::rgba(0.2, 0.2, 0.4, 1.0) } else if context.hover() { Color::rgba(0.8, 0.8, 0.9, 1.0) } else { Color::rgba(0.5, 0.5, 0.7, 1.0) };
         let rect = context.rect();
@@ -447,6 +1160,84 @@ impl<'a> Widget for Button<'a> {
     }
 }
 
+// Frame stats as lines of text on a dark background, for drawing over everything else.
+#[derive(Copy, Clone)]
+pub struct StatsOverlay<'a> {
+    lines: &'a [&'a str],
+    font: FontId,
+    scale: f32,
+}
+
+impl<'a> StatsOverlay<'a> {
+    pub fn new(arena: &'a Arena, stats: &FrameStats, font: FontId, scale: f32) -> &'a StatsOverlay<'a> {
+        let ms = |duration: Duration| duration.as_secs() as f32 * 1000.0 + duration.subsec_nanos() as f32 / 1_000_000.0;
+        let fps = if stats.interval > Duration::default() { 1000.0 / ms(stats.interval) } else { 0.0 };
+        let lines = [
+            format!("{:.0} fps, {:.2} ms", fps, ms(stats.interval)),
+            format!("layout {:.2} ms", ms(stats.layout)),
+            format!("render {:.2} ms", ms(stats.render)),
+            format!("tessellation {:.2} ms", ms(stats.tessellation)),
+            format!("atlas upload {:.2} ms", ms(stats.atlas_upload)),
+            format!("submit {:.2} ms", ms(stats.submit)),
+            format!("gpu {}", stats.gpu.map_or("-".to_string(), |gpu| format!("{:.2} ms", ms(gpu)))),
+            format!("{} vertices, {} draw calls", stats.vertices, stats.draw_calls),
+        ];
+        let lines: Vec<&str> = lines.iter().map(|line| &*arena.alloc_str(line)).collect();
+        arena.alloc(StatsOverlay { lines: arena.alloc_slice(&lines), font, scale })
+    }
+}
+
+impl<'a> StatsOverlay<'a> {
+    fn size(&self, graphics: &Graphics) -> (f32, f32) {
+        let mut width: f32 = 0.0;
+        let mut height: f32 = 0.0;
+        for line in self.lines.iter() {
+            let (line_width, line_height) = graphics.text_size(line, self.font, self.scale);
+            width = width.max(line_width);
+            height += line_height;
+        }
+        (width + 10.0, height + 10.0)
+    }
+}
+
+impl<'a> Widget for StatsOverlay<'a> {
+    fn layout(&self, mut context: LayoutContext, constraints: Constraints) {
+        let (width, height) = self.size(context.graphics());
+        let (width, height) = constraints.constrain(width, height);
+        context.size(width, height);
+    }
+
+    fn intrinsic_width(&self, graphics: &Graphics, _height: f32) -> (f32, f32) {
+        let width = self.size(graphics).0;
+        (width, width)
+    }
+
+    fn intrinsic_height(&self, graphics: &Graphics, _width: f32) -> (f32, f32) {
+        let height = self.size(graphics).1;
+        (height, height)
+    }
+
+    fn hash_layout(&self, hasher: &mut DefaultHasher) -> bool {
+        self.lines.hash(hasher);
+        self.font.hash(hasher);
+        self.scale.to_bits().hash(hasher);
+        true
+    }
+
+    fn render(&self, mut context: RenderContext) {
+        // keep the stats current while they are shown, even when nothing else changes
+        context.request_frame();
+        let rect = context.rect();
+        let graphics = context.graphics();
+        graphics.rect_fill([rect.x, rect.y], [rect.width, rect.height], Color::rgba(0.0, 0.0, 0.0, 0.6));
+        let mut y = rect.y + 5.0;
+        for line in self.lines.iter() {
+            graphics.text([rect.x + 5.0, y], line, self.font, self.scale, Color::rgba(1.0, 1.0, 1.0, 1.0));
+            y += graphics.text_size(line, self.font, self.scale).1;
+        }
+    }
+}
+
 
 pub struct MouseState {
     left: bool,
@@ -610,3 +1401,289 @@ pub enum MouseButton {
     Middle,
     Right,
 }
+
+#[cfg(test)]
+fn test_ui() -> UI {
+    UI::with_graphics(Graphics::headless())
+}
+
+// Records what happens to a TestLeaf across frames.
+#[cfg(test)]
+#[derive(Default)]
+struct Probe {
+    layouts: std::cell::Cell<usize>,
+    clicks: Rc<std::cell::Cell<usize>>,
+    rect: std::cell::Cell<Option<Rect>>,
+}
+
+#[cfg(test)]
+#[derive(Copy, Clone)]
+struct TestLeaf<'a> {
+    width: f32,
+    height: f32,
+    probe: &'a Probe,
+}
+
+#[cfg(test)]
+impl<'a> Widget for TestLeaf<'a> {
+    fn layout(&self, mut context: LayoutContext, constraints: Constraints) {
+        self.probe.layouts.set(self.probe.layouts.get() + 1);
+        let clicks = self.probe.clicks.clone();
+        context.listen(move |_, _| { clicks.set(clicks.get() + 1); true });
+        let (width, height) = constraints.constrain(self.width, self.height);
+        context.size(width, height);
+    }
+
+    fn intrinsic_width(&self, _graphics: &Graphics, _height: f32) -> (f32, f32) {
+        (self.width, self.width)
+    }
+
+    fn intrinsic_height(&self, _graphics: &Graphics, _width: f32) -> (f32, f32) {
+        (self.height, self.height)
+    }
+
+    fn hash_layout(&self, hasher: &mut DefaultHasher) -> bool {
+        self.width.to_bits().hash(hasher);
+        self.height.to_bits().hash(hasher);
+        true
+    }
+
+    fn render(&self, context: RenderContext) {
+        self.probe.rect.set(Some(context.rect()));
+    }
+}
+
+#[cfg(test)]
+fn test_frame(ui: &mut UI, root: &dyn Widget) {
+    ui.layout(800.0, 600.0, root);
+    root.render(RenderContext { ui, index: 0 });
+}
+
+#[test]
+fn test_layout_cache_reuse() {
+    let mut ui = test_ui();
+    let probes = [Probe::default(), Probe::default()];
+    let a = Arena::with_capacity(1024);
+    let tree = Padding::uniform(&a, 5.0, Row::new(&a, 10.0, &[
+        a.alloc(TestLeaf { width: 20.0, height: 10.0, probe: &probes[0] }),
+        a.alloc(TestLeaf { width: 30.0, height: 15.0, probe: &probes[1] }),
+    ]));
+
+    test_frame(&mut ui, tree);
+    let first: Vec<(f32, f32, f32, f32)> = ui.tree.iter().map(|node| (node.rect.x, node.rect.y, node.rect.width, node.rect.height)).collect();
+    test_frame(&mut ui, tree);
+    let second: Vec<(f32, f32, f32, f32)> = ui.tree.iter().map(|node| (node.rect.x, node.rect.y, node.rect.width, node.rect.height)).collect();
+
+    assert_eq!(probes[0].layouts.get(), 1);
+    assert_eq!(probes[1].layouts.get(), 1);
+    assert_eq!(first[0], second[0]);
+    let rect = probes[1].rect.get().unwrap();
+    assert_eq!((rect.x, rect.y, rect.width, rect.height), (35.0, 5.0, 30.0, 15.0));
+}
+
+#[test]
+fn test_layout_cache_edited_leaf() {
+    let mut ui = test_ui();
+    let probes = [Probe::default(), Probe::default(), Probe::default()];
+    let frame = |ui: &mut UI, width: f32| {
+        let a = Arena::with_capacity(1024);
+        let tree = Row::new(&a, 0.0, &[
+            Row::new(&a, 0.0, &[
+                a.alloc(TestLeaf { width: 10.0, height: 10.0, probe: &probes[0] }),
+                a.alloc(TestLeaf { width, height: 10.0, probe: &probes[1] }),
+            ]),
+            a.alloc(TestLeaf { width: 10.0, height: 10.0, probe: &probes[2] }),
+        ]);
+        test_frame(ui, tree);
+    };
+
+    frame(&mut ui, 10.0);
+    frame(&mut ui, 25.0);
+    // only the edited leaf is laid out again, while its ancestors are re-laid out around
+    // the cached layouts of its siblings
+    assert_eq!(probes[0].layouts.get(), 1);
+    assert_eq!(probes[1].layouts.get(), 2);
+    assert_eq!(probes[2].layouts.get(), 1);
+    assert_eq!(probes[1].rect.get().unwrap().width, 25.0);
+    assert_eq!(probes[2].rect.get().unwrap().x, 35.0);
+}
+
+#[test]
+fn test_layout_cache_restore() {
+    let mut ui = test_ui();
+    let probes = [Probe::default(), Probe::default(), Probe::default()];
+    let frame = |ui: &mut UI, width: f32| {
+        let a = Arena::with_capacity(1024);
+        let tree = Row::new(&a, 0.0, &[
+            a.alloc(TestLeaf { width, height: 10.0, probe: &probes[0] }),
+            Padding::uniform(&a, 5.0, Row::new(&a, 0.0, &[
+                a.alloc(TestLeaf { width: 10.0, height: 10.0, probe: &probes[1] }),
+                a.alloc(TestLeaf { width: 10.0, height: 10.0, probe: &probes[2] }),
+            ])),
+        ]);
+        test_frame(ui, tree);
+    };
+
+    frame(&mut ui, 10.0);
+    frame(&mut ui, 50.0);
+    // the padded row is restored from the cache, and still moves with its new offset
+    assert_eq!(probes[2].layouts.get(), 1);
+    let rect = probes[2].rect.get().unwrap();
+    assert_eq!((rect.x, rect.y), (65.0, 5.0));
+
+    // and keeps its handlers
+    ui.cursor(rect.x + 1.0, rect.y + 1.0);
+    ui.input(Input::MouseDown(MouseButton::Left));
+    assert_eq!(probes[2].clicks.get(), 1);
+    assert_eq!(probes[1].clicks.get(), 0);
+}
+
+#[test]
+fn test_layout_cache_relayout() {
+    // lays its child out twice, leaving the first layout's nodes behind in the tree
+    #[derive(Copy, Clone)]
+    struct Twice<'a> {
+        child: &'a dyn Widget,
+    }
+
+    impl<'a> Widget for Twice<'a> {
+        fn layout(&self, mut context: LayoutContext, _constraints: Constraints) {
+            context.children(1);
+            context.layout_child(0, self.child, Constraints::loose(10.0, 10.0));
+            context.layout_child(0, self.child, Constraints::tight(20.0, 20.0));
+            context.size(20.0, 20.0);
+        }
+
+        fn hash_layout(&self, hasher: &mut DefaultHasher) -> bool {
+            self.child.hash_layout(hasher)
+        }
+
+        fn render(&self, mut context: RenderContext) {
+            self.child.render(context.child(0));
+        }
+    }
+
+    let mut ui = test_ui();
+    let probe = Probe::default();
+    let frame = |ui: &mut UI, width: f32| {
+        let a = Arena::with_capacity(1024);
+        let leaf = a.alloc(TestLeaf { width: 5.0, height: 5.0, probe: &probe });
+        test_frame(ui, Row::new(&a, 0.0, &[
+            SizedBox::spacer(&a, width, 10.0),
+            a.alloc(Twice { child: leaf }),
+        ]));
+    };
+
+    frame(&mut ui, 10.0);
+    frame(&mut ui, 20.0);
+    // both layouts of the leaf are cached, and the first one isn't kept with the row
+    assert_eq!(probe.layouts.get(), 2);
+    let rect = probe.rect.get().unwrap();
+    assert_eq!((rect.x, rect.width), (20.0, 20.0));
+    let largest = ui.layout_cache.values().flat_map(|entries| entries.iter()).map(|cached| cached.nodes.len()).max();
+    assert_eq!(largest, Some(3));
+}
+
+#[test]
+fn test_flex_slots() {
+    let mut ui = test_ui();
+    let probes = [Probe::default(), Probe::default(), Probe::default()];
+    let a = Arena::with_capacity(1024);
+    let leaf = |width, probe| a.alloc(TestLeaf { width, height: 10.0, probe }) as &dyn Widget;
+    // 100 left over is shared 1:3 between the growing items
+    let tree = SizedBox::new(&a, 250.0, 100.0, Flex::new(&a, Direction::Row, &[
+        FlexItem::new(leaf(50.0, &probes[0])).grow(1.0),
+        FlexItem::new(leaf(50.0, &probes[1])),
+        FlexItem::new(leaf(50.0, &probes[2])).grow(3.0),
+    ]));
+    test_frame(&mut ui, tree);
+    let widths: Vec<f32> = probes.iter().map(|probe| probe.rect.get().unwrap().width).collect();
+    assert_eq!(widths, [75.0, 50.0, 125.0]);
+    // each item is laid out once, at its slot
+    assert!(probes.iter().all(|probe| probe.layouts.get() == 1));
+    assert_eq!(probes[2].rect.get().unwrap().x, 125.0);
+
+    // 60 missing is taken from the items by their shrink factors weighted by their basis
+    let tree = SizedBox::new(&a, 100.0, 100.0, Flex::new(&a, Direction::Row, &[
+        FlexItem::new(leaf(40.0, &probes[0])),
+        FlexItem::new(leaf(80.0, &probes[1])),
+        FlexItem::new(leaf(40.0, &probes[2])).shrink(0.0),
+    ]));
+    test_frame(&mut ui, tree);
+    let widths: Vec<f32> = probes.iter().map(|probe| probe.rect.get().unwrap().width).collect();
+    assert_eq!(widths, [20.0, 40.0, 40.0]);
+}
+
+#[test]
+fn test_constraints() {
+    let constraints = Constraints { min: (10.0, 10.0), max: (100.0, 50.0) };
+    assert_eq!(constraints.constrain(150.0, 5.0), (100.0, 10.0));
+    assert_eq!(constraints.constrain(40.0, 30.0), (40.0, 30.0));
+    assert_eq!(Constraints::tight(20.0, 30.0).constrain(0.0, 100.0), (20.0, 30.0));
+
+    assert_eq!(constraints.deflate(20.0, 20.0), Constraints { min: (0.0, 0.0), max: (80.0, 30.0) });
+    // a padding larger than the maximum leaves no room, rather than a negative one
+    assert_eq!(constraints.deflate(5.0, 60.0), Constraints { min: (5.0, 0.0), max: (95.0, 0.0) });
+    let unbounded = Constraints::loose(f32::INFINITY, 50.0).deflate(10.0, 10.0);
+    assert_eq!(unbounded, Constraints { min: (0.0, 0.0), max: (f32::INFINITY, 40.0) });
+}
+
+#[test]
+fn test_flex_justify() {
+    let mut ui = test_ui();
+    let probes = [Probe::default(), Probe::default(), Probe::default()];
+    let cases = [
+        (Justify::Start, [0.0, 10.0, 20.0]),
+        (Justify::End, [70.0, 80.0, 90.0]),
+        (Justify::Center, [35.0, 45.0, 55.0]),
+        (Justify::SpaceBetween, [0.0, 45.0, 90.0]),
+        (Justify::SpaceAround, [70.0 / 6.0, 45.0, 90.0 - 70.0 / 6.0]),
+        (Justify::SpaceEvenly, [17.5, 45.0, 72.5]),
+    ];
+    for &(justify, offsets) in cases.iter() {
+        let a = Arena::with_capacity(1024);
+        let leaf = |probe| a.alloc(TestLeaf { width: 10.0, height: 10.0, probe }) as &dyn Widget;
+        let items = [FlexItem::new(leaf(&probes[0])), FlexItem::new(leaf(&probes[1])), FlexItem::new(leaf(&probes[2]))];
+        test_frame(&mut ui, SizedBox::new(&a, 100.0, 50.0, Flex::new(&a, Direction::Row, &items).justify(justify)));
+        let xs: Vec<f32> = probes.iter().map(|probe| probe.rect.get().unwrap().x).collect();
+        for (x, offset) in xs.iter().zip(offsets.iter()) {
+            assert!((x - offset).abs() < 1e-4, "{:?}: {:?} != {:?}", justify, xs, offsets);
+        }
+    }
+}
+
+#[test]
+fn test_flex_stretch() {
+    let mut ui = test_ui();
+    let probes = [Probe::default(), Probe::default()];
+    for &(align, heights) in [(Align::Start, [10.0, 20.0]), (Align::Stretch, [50.0, 50.0])].iter() {
+        let a = Arena::with_capacity(1024);
+        let tree = SizedBox::new(&a, 100.0, 50.0, Flex::new(&a, Direction::Row, &[
+            FlexItem::new(a.alloc(TestLeaf { width: 10.0, height: 10.0, probe: &probes[0] })),
+            FlexItem::new(a.alloc(TestLeaf { width: 10.0, height: 20.0, probe: &probes[1] })),
+        ]).align(align));
+        test_frame(&mut ui, tree);
+        let rects: Vec<Rect> = probes.iter().map(|probe| probe.rect.get().unwrap()).collect();
+        assert_eq!([rects[0].height, rects[1].height], heights);
+        assert_eq!([rects[0].y, rects[1].y], [0.0, 0.0]);
+    }
+
+    // with several lines, items are stretched to their own line rather than the Flex
+    let a = Arena::with_capacity(1024);
+    let tree = SizedBox::new(&a, 15.0, 50.0, Flex::new(&a, Direction::Row, &[
+        FlexItem::new(a.alloc(TestLeaf { width: 10.0, height: 10.0, probe: &probes[0] })),
+        FlexItem::new(a.alloc(TestLeaf { width: 10.0, height: 20.0, probe: &probes[1] })),
+    ]).align(Align::Stretch).wrap(true));
+    test_frame(&mut ui, tree);
+    let rects: Vec<Rect> = probes.iter().map(|probe| probe.rect.get().unwrap()).collect();
+    assert_eq!([rects[0].height, rects[1].height], [10.0, 20.0]);
+    assert_eq!(rects[1].y, 10.0);
+}
+
+#[test]
+#[should_panic(expected = "aspect ratio must be positive")]
+fn test_aspect_ratio_invalid() {
+    let a = Arena::with_capacity(1024);
+    let leaf = Probe::default();
+    AspectRatio::new(&a, 0.0, a.alloc(TestLeaf { width: 10.0, height: 10.0, probe: &leaf }));
+}